@@ -1,62 +1,196 @@
-//! This is a tool for extracting the latest version of a `.crate`.
+//! This is a tool for extracting `.crate` files.
+//!
+//! By default the latest version of every crate is extracted. Any of the
+//! selection options from `crates_scanner::selection` can be used to choose
+//! other versions, which are extracted side by side. Versions that are no
+//! longer selected are only removed with `--prune`.
+//!
+//! Each crate is extracted atomically with a completion marker, and
+//! directories without a marker are extracted again. `--verify` also checks
+//! that every marker matches the checksum of its `.crate` file and removes
+//! temporary directories left behind by an interrupted run.
+//!
+//! The output uses the same sharded layout as the crates mirror unless
+//! `--layout flat` is given.
 
+use anyhow::{Context, Result};
 use core::sync::atomic::{AtomicU32, Ordering};
+use crates_scanner::extract::{self, Status};
+use crates_scanner::{CrateId, Layout, Selection, Versions};
 use rayon::prelude::*;
-use std::path::Path;
-use std::process::Command;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// A selected crate, its `.crate` file, and where it is extracted to.
+type Selected = (CrateId, PathBuf, PathBuf);
+
+const USAGE: &str = "usage: extract-latest CRATES_PATH OUTPUT_PATH \
+    [--prune] [--verify] [--layout sharded|flat] [selection options]";
 
 fn main() {
-    let mut args = std::env::args().skip(1);
-    let Some(crates_path) = args.next() else {
+    let (selection, args) = match Selection::from_args(Versions::Latest, std::env::args().skip(1)) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("error: {e}\n{USAGE}\n{}", crates_scanner::selection::USAGE);
+            std::process::exit(1);
+        }
+    };
+    let mut prune = false;
+    let mut verify = false;
+    let mut layout = Layout::Sharded;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--prune" => prune = true,
+            "--verify" => verify = true,
+            "--layout" => match args.next().map(|l| l.parse()) {
+                Some(Ok(l)) => layout = l,
+                Some(Err(e)) => {
+                    eprintln!("error: {e}");
+                    std::process::exit(1);
+                }
+                None => {
+                    eprintln!("error: `--layout` requires a value\n{USAGE}");
+                    std::process::exit(1);
+                }
+            },
+            _ if arg.starts_with("--") => {
+                eprintln!("unexpected argument `{arg}`\n{USAGE}");
+                std::process::exit(1);
+            }
+            _ => positional.push(arg),
+        }
+    }
+    let mut positional = positional.into_iter();
+    let Some(crates_path) = positional.next() else {
         eprintln!("first argument must be a path to the crates directory");
         std::process::exit(1);
     };
     let crates_path = Path::new(&crates_path);
-    let Some(output_path) = args.next() else {
+    let Some(output_path) = positional.next() else {
         eprintln!("second argument must be a path to write the source files");
         std::process::exit(1);
     };
     let output_path = Path::new(&output_path);
+    if let Some(arg) = positional.next() {
+        eprintln!("unexpected argument `{arg}`\n{USAGE}");
+        std::process::exit(1);
+    }
 
-    let latest = crates_scanner::collect_latest_crates(&crates_path);
-    eprintln!("total: {}", latest.len());
+    let selected: Vec<_> = selection
+        .collect(crates_path)
+        .into_iter()
+        .map(|path| {
+            let id = CrateId::from_crate_path(&path).unwrap();
+            let crate_out_path = layout.crate_dir(output_path, &id);
+            (id, path, crate_out_path)
+        })
+        .collect();
+    eprintln!("total: {}", selected.len());
+
+    let mut temp_dirs = 0;
+    if verify {
+        temp_dirs = remove_temp_dirs(&selected);
+    }
 
     let extracted = AtomicU32::new(0);
+    let repaired = AtomicU32::new(0);
     let errors = AtomicU32::new(0);
+    let rejected = AtomicU32::new(0);
 
-    latest.par_iter().for_each(|path| {
-        let rel = path.strip_prefix(crates_path).unwrap();
-        let crate_out_path = output_path.join(rel.with_extension(""));
-        if crate_out_path.exists() {
-            return;
-        }
-        let parent = crate_out_path.parent().unwrap();
-        if parent.exists() {
-            // Remove any old versions.
-            std::fs::remove_dir_all(&parent).unwrap();
+    selected.par_iter().for_each(|(_id, path, crate_out_path)| {
+        match extract::check_extracted(path, crate_out_path, verify) {
+            Ok(Status::Complete) => return,
+            Ok(Status::Missing) => eprintln!("extracting to {crate_out_path:?}"),
+            Ok(status) => {
+                repaired.fetch_add(1, Ordering::SeqCst);
+                eprintln!("repairing {status:?} extraction {crate_out_path:?}");
+            }
+            Err(e) => {
+                errors.fetch_add(1, Ordering::SeqCst);
+                eprintln!("error: failed to check {crate_out_path:?}: {e:?}");
+                return;
+            }
         }
-        std::fs::create_dir_all(&parent).unwrap();
-        eprintln!("extracting to {crate_out_path:?}");
-        let status = Command::new("tar")
-            .arg("-xzf")
-            .arg(path)
-            .arg("-C")
-            .arg(&parent)
-            .status()
-            .unwrap();
-        if status.success() {
-            extracted.fetch_add(1, Ordering::SeqCst);
-        } else {
-            errors.fetch_add(1, Ordering::SeqCst);
-            eprintln!("error: failed to extract {path:?} into {crate_out_path:?}");
+        match extract::extract_crate_atomic(path, crate_out_path) {
+            Ok(report) => {
+                extracted.fetch_add(1, Ordering::SeqCst);
+                for r in &report.rejected {
+                    eprintln!("warning: rejected entry in {path:?}: {r}");
+                }
+                rejected.fetch_add(report.rejected.len() as u32, Ordering::SeqCst);
+            }
+            Err(e) => {
+                errors.fetch_add(1, Ordering::SeqCst);
+                eprintln!("error: failed to extract {path:?} into {crate_out_path:?}: {e:?}");
+            }
         }
     });
+
+    let mut pruned = 0;
+    if prune {
+        match prune_unselected(layout, output_path, &selected) {
+            Ok(n) => pruned = n,
+            Err(e) => {
+                errors.fetch_add(1, Ordering::SeqCst);
+                eprintln!("error: failed to prune: {e:#}");
+            }
+        }
+    }
+
     println!(
         "extracted: {}\n\
+        repaired: {}\n\
         errors: {}\n\
+        rejected entries: {}\n\
+        removed temp dirs: {}\n\
+        pruned: {}\n\
         total: {}",
         extracted.load(Ordering::SeqCst),
+        repaired.load(Ordering::SeqCst),
         errors.load(Ordering::SeqCst),
-        latest.len()
+        rejected.load(Ordering::SeqCst),
+        temp_dirs,
+        pruned,
+        selected.len()
     );
 }
+
+/// Removes extracted versions of the selected crates that are not part of
+/// the selection.
+///
+/// Crates that were not selected at all are left alone, so pruning with
+/// `--crate` only touches the named crates.
+fn prune_unselected(layout: Layout, output_path: &Path, selected: &[Selected]) -> Result<u32> {
+    let names: HashSet<_> = selected.iter().map(|(id, ..)| &id.name).collect();
+    let keep: HashSet<_> = selected.iter().map(|(id, ..)| id).collect();
+    let mut pruned = 0;
+    for (id, dir) in layout.enumerate(output_path)? {
+        if names.contains(&id.name) && !keep.contains(&id) {
+            eprintln!("removing {dir:?}");
+            std::fs::remove_dir_all(&dir).with_context(|| format!("failed to remove {dir:?}"))?;
+            pruned += 1;
+        }
+    }
+    Ok(pruned)
+}
+
+/// Removes leftover temporary directories next to the selected crates.
+fn remove_temp_dirs(selected: &[Selected]) -> usize {
+    let parents: HashSet<_> = selected
+        .iter()
+        .map(|(_, _, crate_out_path)| crate_out_path.parent().unwrap())
+        .collect();
+    parents
+        .par_iter()
+        .filter(|dir| dir.exists())
+        .map(|dir| match extract::remove_temp_dirs(dir) {
+            Ok(n) => n,
+            Err(e) => {
+                eprintln!("error: failed to clean up {dir:?}: {e:?}");
+                0
+            }
+        })
+        .sum()
+}
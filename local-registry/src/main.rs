@@ -0,0 +1,181 @@
+//! This is a tool for building a cargo local registry from the crates mirror
+//! and a clone of the crates.io index.
+//!
+//! The registry only lists versions whose `.crate` file is in the mirror,
+//! so cargo never resolves to something it can't download. The `.crate`
+//! files are symlinked into the registry instead of copied.
+//!
+//! Every version is included by default. The selection options from
+//! `crates_scanner::selection` can restrict that, for example with `--as-of`
+//! to resolve as if it were an earlier date, but keep in mind that
+//! dependencies that are not selected can't be resolved.
+//!
+//! Pass the registry to the scanners that run cargo with `--registry PATH`.
+//! The registry is built in a temporary directory and then moved into
+//! place, so an existing registry at the output path is replaced in one
+//! step.
+
+use anyhow::{bail, Context, Result};
+use core::sync::atomic::{AtomicU32, Ordering};
+use crates_scanner::{layout, CrateId, Selection, Versions};
+use rayon::prelude::*;
+use semver::Version;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "usage: local-registry CRATES_PATH INDEX_PATH OUTPUT_PATH [selection options]";
+
+fn main() {
+    let (selection, args) = match Selection::from_args(Versions::All, std::env::args().skip(1)) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("error: {e}\n{USAGE}\n{}", crates_scanner::selection::USAGE);
+            std::process::exit(1);
+        }
+    };
+    if let Some(arg) = args.iter().find(|arg| arg.starts_with("--")) {
+        eprintln!("unexpected argument `{arg}`\n{USAGE}");
+        std::process::exit(1);
+    }
+    let [crates_path, index_path, output_path] = match <[String; 3]>::try_from(args) {
+        Ok(paths) => paths.map(PathBuf::from),
+        Err(_) => {
+            eprintln!("{USAGE}");
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = build(&selection, &crates_path, &index_path, &output_path) {
+        eprintln!("error: {e:?}");
+        std::process::exit(1);
+    }
+}
+
+fn build(
+    selection: &Selection,
+    crates_path: &Path,
+    index_path: &Path,
+    output_path: &Path,
+) -> Result<()> {
+    if output_path.exists() && !output_path.join("index").exists() {
+        bail!("{output_path:?} exists but is not a local registry, refusing to replace it");
+    }
+    let mut by_name: BTreeMap<String, HashMap<Version, PathBuf>> = BTreeMap::new();
+    for path in selection.collect(crates_path) {
+        let id = CrateId::from_crate_path(&path).unwrap();
+        let path = path.canonicalize()?;
+        by_name.entry(id.name).or_default().insert(id.version, path);
+    }
+    eprintln!("total: {} crates", by_name.len());
+
+    let parent = match output_path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(parent)?;
+    let tmp = tempfile::Builder::new()
+        .prefix(".local-registry-")
+        .tempdir_in(parent)?;
+    let registry = tmp.path().join("registry");
+    fs::create_dir_all(registry.join("index"))?;
+
+    let linked = AtomicU32::new(0);
+    let dropped = AtomicU32::new(0);
+    let not_in_index = AtomicU32::new(0);
+    let errors = AtomicU32::new(0);
+    by_name.par_iter().for_each(|(name, versions)| {
+        match add_crate(&registry, index_path, name, versions) {
+            Ok(added) => {
+                linked.fetch_add(added.linked, Ordering::SeqCst);
+                dropped.fetch_add(added.dropped, Ordering::SeqCst);
+                not_in_index.fetch_add(added.not_in_index, Ordering::SeqCst);
+            }
+            Err(e) => {
+                errors.fetch_add(1, Ordering::SeqCst);
+                eprintln!("error: failed to add `{name}`: {e:?}");
+            }
+        }
+    });
+
+    if output_path.exists() {
+        fs::remove_dir_all(output_path)
+            .with_context(|| format!("failed to remove old registry {output_path:?}"))?;
+    }
+    fs::rename(&registry, output_path)
+        .with_context(|| format!("failed to move registry into {output_path:?}"))?;
+
+    println!(
+        "versions: {}\n\
+        index entries without a .crate: {}\n\
+        .crate files not in the index: {}\n\
+        errors: {}",
+        linked.load(Ordering::SeqCst),
+        dropped.load(Ordering::SeqCst),
+        not_in_index.load(Ordering::SeqCst),
+        errors.load(Ordering::SeqCst),
+    );
+    Ok(())
+}
+
+/// Counts for one crate added to the registry.
+struct Added {
+    linked: u32,
+    dropped: u32,
+    not_in_index: u32,
+}
+
+/// Writes the index file for `name`, keeping only the versions in
+/// `versions`, and links their `.crate` files into the registry.
+fn add_crate(
+    registry: &Path,
+    index_path: &Path,
+    name: &str,
+    versions: &HashMap<Version, PathBuf>,
+) -> Result<Added> {
+    let index_file = layout::shard(name).join(name.to_lowercase());
+    let contents = match fs::read_to_string(index_path.join(&index_file)) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("warning: `{name}` is not in the index, skipping");
+            return Ok(Added {
+                linked: 0,
+                dropped: 0,
+                not_in_index: versions.len() as u32,
+            });
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut added = Added {
+        linked: 0,
+        dropped: 0,
+        not_in_index: 0,
+    };
+    let mut lines = String::new();
+    for line in contents.lines().filter(|line| !line.is_empty()) {
+        let entry: serde_json::Value = serde_json::from_str(line)?;
+        let version = entry["vers"]
+            .as_str()
+            .with_context(|| format!("index entry without `vers`: {line}"))?;
+        let version = Version::parse(version)?;
+        let Some(crate_path) = versions.get(&version) else {
+            added.dropped += 1;
+            continue;
+        };
+        lines.push_str(line);
+        lines.push('\n');
+        let link = registry.join(format!("{name}-{version}.crate"));
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(crate_path, &link)?;
+        #[cfg(not(unix))]
+        fs::copy(crate_path, &link)?;
+        added.linked += 1;
+    }
+    added.not_in_index = versions.len() as u32 - added.linked;
+    if !lines.is_empty() {
+        let dst = registry.join("index").join(&index_file);
+        fs::create_dir_all(dst.parent().unwrap())?;
+        fs::write(&dst, lines)?;
+    }
+    Ok(added)
+}
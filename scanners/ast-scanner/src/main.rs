@@ -1,41 +1,47 @@
 //! Scans rust source with an AST visitor.
+//!
+//! This finds string literals where a `\` line continuation is followed by
+//! an empty line, which the continuation silently skips. See
+//! `crates_scanner::ast` for writing other scans.
 
-use anyhow::Result;
-use std::path::Path;
-use syn::visit::Visit;
+use crates_scanner::ast::{AstScanner, Context};
+use crates_scanner::findings::Span;
+use crates_scanner::literal::Literal;
 
-fn main() {
-    let crates_path = std::env::args()
-        .skip(1)
-        .next()
-        .expect("first argument must be a path to the crates directory");
-
-    crates_scanner::scan_compressed(
-        Path::new(&crates_path),
-        crates_scanner::Versions::All,
-        |path| path.extension().map_or(false, |ext| ext == "rs"),
-        scan_rust,
-    );
-}
+struct Continuations;
 
-fn scan_rust(path: &Path, contents: &str) -> Result<()> {
-    let f = match syn::parse_file(&contents) {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("failed to parse {path:?}: {e}");
-            return Ok(());
+impl AstScanner for Continuations {
+    fn lit(&self, cx: &mut Context<'_>, lit: &syn::Lit) {
+        let token = match lit {
+            syn::Lit::Str(s) => s.token(),
+            syn::Lit::ByteStr(s) => s.token(),
+            syn::Lit::CStr(s) => s.token(),
+            _ => return,
+        };
+        // Raw strings have no continuations.
+        if !Literal::new(&token).is_some_and(|lit| lit.kind.has_escapes()) {
+            return;
+        }
+        let start = Span::from(token.span()).start.offset(cx.contents);
+        if let Some(found) = continuation_before_empty_line(cx.source(token.span())) {
+            let span = Span::from_offsets(cx.contents, start + found.start, start + found.end);
+            cx.report(span, "string continuation followed by an empty line");
         }
-    };
-    Visitor { file: &contents }.visit_file(&f);
-    Ok(())
+    }
 }
 
-struct Visitor<'a> {
-    file: &'a str,
+fn main() {
+    crates_scanner::ast::run(&Continuations);
 }
 
-impl<'ast> syn::visit::Visit<'ast> for Visitor<'_> {
-    fn visit_lit_str(&mut self, i: &'ast syn::LitStr) {
-        let s = i.span();
-    }
+/// Finds a `\` at the end of a line followed by an empty line, which skips
+/// the empty line along with the whitespace.
+fn continuation_before_empty_line(s: &str) -> Option<std::ops::Range<usize>> {
+    s.match_indices("\\\n").find_map(|(i, m)| {
+        let rest = &s[i + m.len()..];
+        let spaces = rest.len() - rest.trim_start_matches(' ').len();
+        rest[spaces..]
+            .starts_with('\n')
+            .then(|| i..i + m.len() + spaces + 1)
+    })
 }
@@ -0,0 +1,89 @@
+//! Runs a cargo command with two cargo binaries and compares the results.
+//!
+//! For example, to compare `cargo tree` between the installed cargo and a
+//! local build:
+//!
+//! ```text
+//! cargo-diff src /path/to/cargo -- tree -e features
+//! ```
+
+use anyhow::{bail, format_err, Result};
+use crates_scanner::cargo::CargoRunner;
+use crates_scanner::differential::{Capture, Differential, LockfileDiff, SortLines};
+use std::path::Path;
+
+const USAGE: &str = "\
+usage: cargo-diff SRC_PATH CARGO_PATH [options] [cargo runner options] -- CARGO_ARGS...
+
+options:
+    --capture FILE    compare this file instead of stdout
+    --remove FILE     remove this file from the crate before running
+    --sort-lines      ignore the order of lines
+    --lockfile        compare the captured file as a Cargo.lock";
+
+fn main() {
+    let (src_path, differential) = match parse_args() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("error: {e}\n{USAGE}\n\n{}", crates_scanner::cargo::USAGE);
+            std::process::exit(1);
+        }
+    };
+    let scanned = crates_scanner::scan_uncompressed(Path::new(&src_path), |path| {
+        differential.run(path)?.check(path)
+    });
+    if let Err(e) = scanned {
+        eprintln!("error: {e:#}");
+        std::process::exit(1);
+    }
+}
+
+fn parse_args() -> Result<(String, Differential)> {
+    let mut args: Vec<_> = std::env::args().skip(1).collect();
+    let Some(sep) = args.iter().position(|a| a == "--") else {
+        bail!("missing `--` before the cargo command");
+    };
+    let cargo_args = args.split_off(sep + 1);
+    args.pop();
+    if cargo_args.is_empty() {
+        bail!("no cargo command given");
+    }
+
+    let (old, args) = CargoRunner::from_args("cargo", args)?;
+    let mut capture = Capture::Stdout;
+    let mut remove = Vec::new();
+    let mut sort_lines = false;
+    let mut lockfile = false;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format_err!("`{arg}` requires a value"))
+        };
+        match arg.as_str() {
+            "--capture" => capture = Capture::File(value()?.into()),
+            "--remove" => remove.push(value()?),
+            "--sort-lines" => sort_lines = true,
+            "--lockfile" => lockfile = true,
+            s if s.starts_with("--") => bail!("unknown option `{s}`"),
+            _ => positional.push(arg.clone()),
+        }
+    }
+    let [src_path, cargo_path] = <[String; 2]>::try_from(positional)
+        .map_err(|_| format_err!("expected SRC_PATH and CARGO_PATH"))?;
+
+    let new = old.with_cargo(cargo_path);
+    let cargo_args: Vec<_> = cargo_args.iter().map(|s| s.as_str()).collect();
+    let mut differential = Differential::new(old, new, &cargo_args).capture(capture);
+    for path in remove {
+        differential = differential.remove(path);
+    }
+    if sort_lines {
+        differential = differential.normalizer(SortLines);
+    }
+    if lockfile {
+        differential = differential.differ(LockfileDiff);
+    }
+    Ok((src_path, differential))
+}
@@ -0,0 +1,312 @@
+//! Counts the attributes and `cfg` predicates crates use.
+//!
+//! Every attribute is counted by its path, like `derive`, `serde` or
+//! `rustfmt::skip`, and every `cfg` predicate by its options, like `unix`
+//! or `feature = "std"`, with `all`, `any` and `not` taken apart.
+//! Predicates come from `#[cfg]`, `#[cfg_attr]` (whose attributes are
+//! counted too) and `cfg!`, including attributes inside macro invocations
+//! like `cfg_if!`.
+//!
+//! A `feature = "..."` the manifest doesn't declare, either in
+//! `[features]` or as an optional dependency, is reported as a finding.
+
+use crates_scanner::ast::{AstScanner, Context};
+use crates_scanner::manifest::Manifest;
+use crates_scanner::results::Tally;
+use proc_macro2::{Delimiter, Group, TokenStream, TokenTree};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{Meta, Token};
+
+struct CfgCensus {
+    attributes: Tally,
+    predicates: Tally,
+}
+
+impl AstScanner for CfgCensus {
+    fn attribute(&self, cx: &mut Context<'_>, attr: &syn::Attribute) {
+        self.meta(cx, &attr.meta);
+    }
+
+    fn mac(&self, cx: &mut Context<'_>, mac: &syn::Macro) {
+        if mac.path.is_ident("cfg") {
+            self.predicates_in(cx, mac.tokens.clone());
+        } else {
+            // syn doesn't parse macro arguments, so look for attributes and
+            // `cfg!` in their tokens.
+            self.tokens(cx, mac.tokens.clone());
+        }
+    }
+}
+
+fn main() {
+    let census = CfgCensus {
+        attributes: Tally::new(5),
+        predicates: Tally::new(5),
+    };
+    crates_scanner::ast::run(&census);
+    census.attributes.print("attributes");
+    census.predicates.print("cfg predicates");
+}
+
+impl CfgCensus {
+    fn meta(&self, cx: &mut Context<'_>, meta: &Meta) {
+        let name = path_name(meta.path());
+        self.attributes.add(&name, cx.krate);
+        let Meta::List(list) = meta else { return };
+        match name.as_str() {
+            "cfg" => self.predicates_in(cx, list.tokens.clone()),
+            "cfg_attr" => {
+                let Ok(args) = parse_metas(list.tokens.clone()) else {
+                    return;
+                };
+                let mut args = args.into_iter();
+                if let Some(predicate) = args.next() {
+                    self.predicate(cx, &predicate);
+                }
+                for attr in args {
+                    self.meta(cx, &attr);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn predicates_in(&self, cx: &mut Context<'_>, tokens: TokenStream) {
+        for predicate in parse_metas(tokens).into_iter().flatten() {
+            self.predicate(cx, &predicate);
+        }
+    }
+
+    fn predicate(&self, cx: &mut Context<'_>, predicate: &Meta) {
+        match predicate {
+            Meta::Path(path) => self.predicates.add(path_name(path), cx.krate),
+            Meta::NameValue(nv) => {
+                let name = path_name(&nv.path);
+                let syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(value),
+                    ..
+                }) = &nv.value
+                else {
+                    return;
+                };
+                let value = value.value();
+                self.predicates.add(format!("{name} = {value:?}"), cx.krate);
+                if name == "feature" && cx.manifest.is_some_and(|m| !declares(m, &value)) {
+                    cx.report(nv.value.span(), format!("undeclared feature `{value}`"));
+                }
+            }
+            Meta::List(list) => {
+                let name = path_name(&list.path);
+                if matches!(name.as_str(), "all" | "any" | "not") {
+                    self.predicates_in(cx, list.tokens.clone());
+                } else {
+                    // Like `version("1.80")` or `accessible(...)`.
+                    self.predicates.add(format!("{name}(..)"), cx.krate);
+                }
+            }
+        }
+    }
+
+    /// Finds `#[...]` and `#![...]` attributes and `cfg!(...)` in the
+    /// arguments of a macro, which may be in nested groups.
+    fn tokens(&self, cx: &mut Context<'_>, tokens: TokenStream) {
+        let tokens: Vec<_> = tokens.into_iter().collect();
+        for (i, tt) in tokens.iter().enumerate() {
+            if let Some((_, attr)) = attribute_at(&tokens, i) {
+                // Not an attribute if it doesn't parse, like `#[$meta]` in a
+                // macro_rules! body.
+                if let Ok(meta) = syn::parse2::<Meta>(attr.stream()) {
+                    self.meta(cx, &meta);
+                }
+                continue;
+            }
+            match (tt, tokens.get(i + 1), tokens.get(i + 2)) {
+                (
+                    TokenTree::Ident(ident),
+                    Some(TokenTree::Punct(bang)),
+                    Some(TokenTree::Group(g)),
+                ) if ident == "cfg" && bang.as_char() == '!' => {
+                    self.predicates_in(cx, g.stream());
+                }
+                (TokenTree::Group(g), _, _) if !is_attribute(&tokens, i) => {
+                    self.tokens(cx, g.stream());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Returns the brackets of the attribute starting at `i`, and where they
+/// are, if `#[...]` or `#![...]` starts there.
+fn attribute_at(tokens: &[TokenTree], i: usize) -> Option<(usize, &Group)> {
+    let is_punct =
+        |j: usize, c| matches!(tokens.get(j), Some(TokenTree::Punct(p)) if p.as_char() == c);
+    if !is_punct(i, '#') {
+        return None;
+    }
+    let brackets = if is_punct(i + 1, '!') { i + 2 } else { i + 1 };
+    match tokens.get(brackets) {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => Some((brackets, g)),
+        _ => None,
+    }
+}
+
+/// Whether the group at `i` is the brackets of an attribute, which were
+/// handled with the `#`.
+fn is_attribute(tokens: &[TokenTree], i: usize) -> bool {
+    (1..=2).any(|back| {
+        i >= back && attribute_at(tokens, i - back).is_some_and(|(brackets, _)| brackets == i)
+    })
+}
+
+fn parse_metas(tokens: TokenStream) -> syn::Result<Punctuated<Meta, Token![,]>> {
+    Punctuated::<Meta, Token![,]>::parse_terminated.parse2(tokens)
+}
+
+/// Writes a path like `rustfmt::skip`, without a leading `::`.
+fn path_name(path: &syn::Path) -> String {
+    let segments: Vec<_> = path.segments.iter().map(|s| s.ident.to_string()).collect();
+    segments.join("::")
+}
+
+/// Whether `feature` is declared in `[features]`, or is the implicit
+/// feature of an optional dependency, which cargo doesn't create if any
+/// feature refers to the dependency with `dep:`.
+fn declares(manifest: &Manifest, feature: &str) -> bool {
+    if manifest.features.contains_key(feature) {
+        return true;
+    }
+    let dep = format!("dep:{feature}");
+    manifest
+        .dependencies
+        .iter()
+        .any(|d| d.optional && d.name == feature)
+        && !manifest.features.values().flatten().any(|v| *v == dep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crates_scanner::modules::CrateFiles;
+    use crates_scanner::CrateSources;
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+
+    fn manifest(rest: &str) -> Manifest {
+        let contents = format!("[package]\nname = \"c\"\nversion = \"1.0.0\"\n{rest}");
+        Manifest::parse(&contents, &[]).unwrap()
+    }
+
+    /// Scans `lib` as the library of a crate whose manifest has `rest`
+    /// after its `[package]`, returning the census and the findings.
+    fn scan(rest: &str, lib: &str) -> (CfgCensus, Vec<String>) {
+        let manifest = format!("[package]\nname = \"c\"\nversion = \"1.0.0\"\n{rest}");
+        let sources = BTreeMap::from([
+            (PathBuf::from("c-1.0.0/Cargo.toml"), manifest),
+            (PathBuf::from("c-1.0.0/src/lib.rs"), lib.to_string()),
+        ]);
+        let krate = CrateSources {
+            path: PathBuf::from("c-1.0.0"),
+            files: sources.keys().cloned().collect(),
+            sources,
+        };
+        let census = CfgCensus {
+            attributes: Tally::new(5),
+            predicates: Tally::new(5),
+        };
+        let path = Path::new("c-1.0.0/src/lib.rs");
+        let (_, findings) =
+            crates_scanner::ast::scan_file(&census, &CrateFiles::new(&krate), path, lib).unwrap();
+        let messages = findings.into_iter().map(|f| f.message).collect();
+        (census, messages)
+    }
+
+    #[test]
+    fn declared_in_features() {
+        let m = manifest("[features]\ndefault = [\"std\"]\nstd = []\n");
+        assert!(declares(&m, "std"));
+        assert!(declares(&m, "default"));
+        assert!(!declares(&m, "alloc"));
+    }
+
+    #[test]
+    fn optional_dependency() {
+        let m = manifest(
+            "[dependencies]\n\
+            rand = { version = \"0.8\", optional = true }\n\
+            log = \"0.4\"\n",
+        );
+        assert!(declares(&m, "rand"));
+        // Only optional dependencies are features.
+        assert!(!declares(&m, "log"));
+    }
+
+    #[test]
+    fn dep_reference_removes_implicit_feature() {
+        let m = manifest(
+            "[features]\n\
+            logging = [\"dep:log\"]\n\
+            \n\
+            [dependencies]\n\
+            log = { version = \"0.4\", optional = true }\n\
+            rand = { version = \"0.8\", optional = true }\n",
+        );
+        assert!(declares(&m, "logging"));
+        assert!(!declares(&m, "log"));
+        assert!(declares(&m, "rand"));
+    }
+
+    #[test]
+    fn undeclared_features_are_reported() {
+        let (census, findings) = scan(
+            "[features]\nstd = []\n",
+            "#[cfg(feature = \"std\")] fn a() {}\n\
+            #[cfg(all(unix, feature = \"sdt\"))] fn b() {}\n",
+        );
+        assert_eq!(findings, ["undeclared feature `sdt`"]);
+        assert_eq!(census.predicates.count("feature = \"std\""), 1);
+        assert_eq!(census.predicates.count("unix"), 1);
+    }
+
+    #[test]
+    fn inner_attributes_in_cfg_if() {
+        let (census, findings) = scan(
+            "",
+            "cfg_if::cfg_if! {\n\
+                if #[cfg(unix)] {\n\
+                    mod imp {\n\
+                        #![cfg(feature = \"inner\")]\n\
+                        #![allow(dead_code)]\n\
+                    }\n\
+                }\n\
+            }\n",
+        );
+        assert_eq!(census.attributes.count("cfg"), 2);
+        assert_eq!(census.attributes.count("allow"), 1);
+        assert_eq!(census.predicates.count("unix"), 1);
+        assert_eq!(census.predicates.count("feature = \"inner\""), 1);
+        assert_eq!(findings, ["undeclared feature `inner`"]);
+    }
+
+    #[test]
+    fn metavariable_attributes_in_macro_rules() {
+        let (census, findings) = scan(
+            "",
+            "macro_rules! item {\n\
+                ($meta:meta, $name:ident) => {\n\
+                    #[$meta]\n\
+                    #[cfg(test)]\n\
+                    fn $name() { let _ = cfg!(debug_assertions); }\n\
+                };\n\
+            }\n",
+        );
+        // `#[$meta]` isn't an attribute until the macro is expanded, but
+        // the attributes next to it still count.
+        assert_eq!(census.attributes.categories(), ["cfg"]);
+        assert_eq!(census.predicates.categories(), ["debug_assertions", "test"]);
+        assert!(findings.is_empty());
+    }
+}
@@ -1,72 +1,59 @@
 //! Compares Cargo.lock generated with different versions.
+//!
+//! Every difference is reported, and a summary at the end counts crates by
+//! the most significant kind of difference, to separate lockfiles that were
+//! only rewritten in a newer format from real resolution changes.
 
-use anyhow::{bail, Result};
-use std::fs;
+use crates_scanner::cargo::CargoRunner;
+use crates_scanner::differential::{Capture, Differential, LockfileDiff, Outcome};
+use crates_scanner::lockfile::Class;
+use std::collections::BTreeMap;
 use std::path::Path;
-use std::process::Command;
+use std::sync::Mutex;
 
 fn main() {
-    let mut args = std::env::args().skip(1);
+    let (stable, args) = match CargoRunner::from_args("cargo", std::env::args().skip(1)) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("error: {e}\n{}", crates_scanner::cargo::USAGE);
+            std::process::exit(1);
+        }
+    };
+    let mut args = args.into_iter();
     let src_path = args
         .next()
         .expect("first argument must be a path to the extracted source directory");
     let cargo_path = args
         .next()
         .expect("second argument must be a path to your locally built cargo");
+    let custom = stable.with_cargo(cargo_path);
 
-    crates_scanner::scan_uncompressed(Path::new(&src_path), |path| {
-        let lock_path = path.join("Cargo.lock");
-        let mut old_lock_path = None;
-        if lock_path.exists() {
-            old_lock_path = Some(lock_path.with_file_name("Cargo.lock.scan-backup"));
-            fs::rename(&lock_path, old_lock_path.as_ref().unwrap()).unwrap();
-        }
-        let result = gen_and_compare(path, &lock_path, &cargo_path);
-        if let Some(original) = &old_lock_path {
-            if let Err(e) = fs::rename(original, &lock_path) {
-                eprintln!("Failed to move to {original:?} from {lock_path:?}: {e:?}",);
-            }
-        }
-        result
-    });
-}
+    // Ignore any Cargo.lock the crate shipped with so both sides resolve
+    // from scratch.
+    let differential = Differential::new(stable, custom, &["generate-lockfile"])
+        .remove("Cargo.lock")
+        .capture(Capture::File("Cargo.lock".into()))
+        .differ(LockfileDiff);
 
-fn gen_and_compare(path: &Path, lock_path: &Path, cargo_path: &str) -> Result<()> {
-    let output = Command::new("cargo")
-        .args(&["generate-lockfile", "-Zno-index-update"])
-        .current_dir(path)
-        .output()
-        .unwrap();
-    if output.status.success() {
-        let stable_path = lock_path.with_file_name("Cargo.lock.stable");
-        fs::rename(&lock_path, &stable_path).unwrap();
-        let output = Command::new(&cargo_path)
-            .args(&["generate-lockfile", "-Zno-index-update"])
-            .current_dir(path)
-            .output()
-            .unwrap();
-        if output.status.success() {
-            let stable = fs::read_to_string(&stable_path).unwrap();
-            let new = fs::read_to_string(&lock_path).unwrap();
-            if stable != new {
-                bail!("{:?} is different !!!!", path);
-            }
-        } else {
-            bail!(
-                "{:?} new failed:\n{}\n{}",
-                path,
-                String::from_utf8_lossy(&output.stdout),
-                String::from_utf8_lossy(&output.stderr)
-            );
+    let classes: Mutex<BTreeMap<Class, u32>> = Mutex::new(BTreeMap::new());
+    let scanned = crates_scanner::scan_uncompressed(Path::new(&src_path), |path| {
+        let outcome = differential.run(path)?;
+        let class = match &outcome {
+            Outcome::Same => Some(Class::Same),
+            Outcome::Different { class, .. } => *class,
+            Outcome::BothFailed { .. } | Outcome::StatusMismatch { .. } => None,
+        };
+        if let Some(class) = class {
+            *classes.lock().unwrap().entry(class).or_default() += 1;
         }
-        drop(fs::remove_file(stable_path));
-    } else {
-        eprintln!(
-            "{:?} failed:\n{}\n{}",
-            path,
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr)
-        );
+        outcome.check(path)
+    });
+    if let Err(e) = scanned {
+        eprintln!("error: {e:#}");
+        std::process::exit(1);
+    }
+    println!("lockfile differences:");
+    for (class, count) in classes.into_inner().unwrap() {
+        println!("    {class}: {count}");
     }
-    Ok(())
 }
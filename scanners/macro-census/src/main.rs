@@ -0,0 +1,691 @@
+//! Counts macro invocations by path and delimiter, and with `--format`, the
+//! kinds of placeholders in the format strings of `format!`-like macros.
+//!
+//! Invocations are found in tokens, so ones inside the arguments of other
+//! macros (like `vec![format!(..)]`) are counted too. Placeholders are
+//! counted by argument (implicit or explicit positional, named, or inline
+//! captured like `{x}`), by the options in their format spec (width,
+//! precision, alignment...) and by formatting trait, and every format
+//! string is counted by the mix of arguments it uses, per macro.
+
+use anyhow::{bail, format_err, Result};
+use crates_scanner::literal::{Literal, LiteralKind};
+use crates_scanner::modules::{is_package_file, parse_kinds, CrateFiles, FileKind};
+use crates_scanner::results::Tally;
+use crates_scanner::{CrateSources, Selection, Versions};
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+
+const USAGE: &str = "\
+usage: macro-census CRATES_PATH [selection options] [--kinds KINDS] [--format]
+       macro-census --extracted SRC_PATH [--kinds KINDS] [--format]
+
+--kinds KINDS  only scan files compiled as one of KINDS, like `lib,bin`
+--format       classify the placeholders in format strings";
+
+/// Macros that take a format string, and how many arguments come before
+/// it.
+const FORMAT_MACROS: &[(&str, usize)] = &[
+    ("format", 0),
+    ("format_args", 0),
+    ("print", 0),
+    ("println", 0),
+    ("eprint", 0),
+    ("eprintln", 0),
+    ("write", 1),
+    ("writeln", 1),
+    ("panic", 0),
+    ("unreachable", 0),
+    ("todo", 0),
+    ("unimplemented", 0),
+    ("assert", 1),
+    ("debug_assert", 1),
+    ("assert_eq", 2),
+    ("assert_ne", 2),
+    ("debug_assert_eq", 2),
+    ("debug_assert_ne", 2),
+    // The `log` and `tracing` macros, after an optional `target: ...`.
+    ("trace", 0),
+    ("debug", 0),
+    ("info", 0),
+    ("warn", 0),
+    ("error", 0),
+];
+
+/// Macros whose only argument, before edition 2021, is the message itself
+/// rather than a format string, so `panic!("{}")` prints `{}`.
+const MESSAGE_MACROS: &[&str] = &["panic", "unreachable", "assert", "debug_assert"];
+
+/// Identifiers that can't end a macro path, so `return !(a)` isn't a
+/// macro call.
+const KEYWORDS: &[&str] = &[
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+    "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where",
+    "while", "async", "await", "dyn", "yield",
+];
+
+struct Args {
+    selection: Selection,
+    path: String,
+    extracted: bool,
+    kinds: Option<HashSet<FileKind>>,
+    format: bool,
+}
+
+struct Census {
+    invocations: Tally,
+    delimiters: Tally,
+    arguments: Tally,
+    specs: Tally,
+    traits: Tally,
+    styles: Tally,
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {e}\n{USAGE}\n{}", crates_scanner::selection::USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    let census = Census {
+        invocations: Tally::new(5),
+        delimiters: Tally::new(5),
+        arguments: Tally::new(5),
+        specs: Tally::new(5),
+        traits: Tally::new(5),
+        styles: Tally::new(5),
+    };
+    let scan_one = |files: Option<&CrateFiles>, krate: &str, path: &Path, contents: &str| {
+        if let Some(kinds) = &args.kinds {
+            let kind = files.and_then(|files| files.kind(path));
+            if !kind.is_some_and(|kind| kinds.contains(&kind)) {
+                return;
+            }
+        }
+        // Without a manifest, assume the oldest edition, which reads the
+        // fewest format strings.
+        let edition = files.and_then(|files| files.edition(path));
+        let format = args.format.then_some(edition.unwrap_or("2015"));
+        let tokens = match TokenStream::from_str(contents) {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("failed to parse {path:?}: {e}");
+                return;
+            }
+        };
+        census.scan(krate, tokens, format);
+    };
+    let scan = |krate: &CrateSources| {
+        let files = (args.kinds.is_some() || args.format).then(|| CrateFiles::new(krate));
+        let name = crates_scanner::crate_name(&krate.path);
+        for (path, contents) in &krate.sources {
+            if path.extension().is_some_and(|ext| ext == "rs") {
+                scan_one(files.as_ref(), &name, path, contents);
+            }
+        }
+        Ok(())
+    };
+    let filter = is_package_file;
+    if args.extracted {
+        if let Err(e) =
+            crates_scanner::scan_uncompressed_crates(Path::new(&args.path), filter, scan)
+        {
+            eprintln!("error: {e:#}");
+            std::process::exit(1);
+        }
+    } else {
+        crates_scanner::scan_compressed_crates(Path::new(&args.path), args.selection, filter, scan);
+    }
+
+    census.invocations.print("invocations");
+    census.delimiters.print("delimiters");
+    if args.format {
+        census.arguments.print("placeholder arguments");
+        census.specs.print("placeholder format specs");
+        census.traits.print("placeholder traits");
+        census.styles.print("format strings by macro and arguments");
+    }
+}
+
+fn parse_args() -> Result<Args> {
+    let (selection, args) = Selection::from_args(Versions::All, std::env::args().skip(1))?;
+    let mut extracted = false;
+    let mut kinds = None;
+    let mut format = false;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--extracted" => extracted = true,
+            "--kinds" => {
+                let list = args
+                    .next()
+                    .ok_or_else(|| format_err!("`--kinds` requires a value"))?;
+                kinds = Some(parse_kinds(&list)?);
+            }
+            "--format" => format = true,
+            s if s.starts_with("--") => bail!("unexpected argument `{s}`"),
+            _ => positional.push(arg),
+        }
+    }
+    let [path] = <[String; 1]>::try_from(positional)
+        .map_err(|_| format_err!("expected CRATES_PATH or --extracted SRC_PATH"))?;
+    Ok(Args {
+        selection,
+        path,
+        extracted,
+        kinds,
+        format,
+    })
+}
+
+impl Census {
+    /// Counts the macro invocations in `tokens`, and their format strings if
+    /// `format` is the edition of the file.
+    fn scan(&self, krate: &str, tokens: TokenStream, format: Option<&str>) {
+        let tokens: Vec<_> = tokens.into_iter().collect();
+        for (i, tt) in tokens.iter().enumerate() {
+            let TokenTree::Group(group) = tt else {
+                continue;
+            };
+            if let Some(path) = macro_path(&tokens[..i]) {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::None => ("", ""),
+                };
+                self.invocations.add(&path, krate);
+                self.delimiters
+                    .add(format!("{path}!{open}..{close}"), krate);
+                if let Some(edition) = format {
+                    self.format_string(krate, edition, &path, group.stream());
+                }
+            }
+            self.scan(krate, group.stream(), format);
+        }
+    }
+
+    fn format_string(&self, krate: &str, edition: &str, path: &str, args: TokenStream) {
+        let name = path.rsplit("::").next().unwrap();
+        let Some(&(_, skip)) = FORMAT_MACROS.iter().find(|(m, _)| *m == name) else {
+            return;
+        };
+        let args = split_args(args);
+        let mut index = skip;
+        // `target: "..."` in `log` macros.
+        if let Some([TokenTree::Ident(_), TokenTree::Punct(colon), ..]) =
+            args.get(index).map(|a| a.as_slice())
+        {
+            if colon.as_char() == ':' && colon.spacing() == Spacing::Alone {
+                index += 1;
+            }
+        }
+        let Some([TokenTree::Literal(lit)]) = args.get(index).map(|a| a.as_slice()) else {
+            return;
+        };
+        let Some(lit) = Literal::new(lit) else { return };
+        if !matches!(lit.kind, LiteralKind::Str | LiteralKind::RawStr) {
+            return;
+        }
+        let more_args = args[index + 1..].iter().any(|arg| !arg.is_empty());
+        if !more_args && edition < "2021" && MESSAGE_MACROS.contains(&name) {
+            return;
+        }
+        let text = lit.text().unwrap();
+        let named: Vec<_> = args[index + 1..]
+            .iter()
+            .filter_map(|arg| match arg.as_slice() {
+                [TokenTree::Ident(name), TokenTree::Punct(eq), _, ..]
+                    if eq.as_char() == '=' && eq.spacing() == Spacing::Alone =>
+                {
+                    Some(name.to_string())
+                }
+                _ => None,
+            })
+            .collect();
+        let Some(placeholders) = placeholders(&text, &named) else {
+            self.styles.add(format!("{name}: invalid"), krate);
+            return;
+        };
+        let mut used = BTreeSet::new();
+        for p in placeholders {
+            self.arguments.add(p.argument.to_string(), krate);
+            for spec in p.specs {
+                self.specs.add(spec, krate);
+            }
+            self.traits.add(p.trait_name, krate);
+            used.insert(p.argument);
+        }
+        let style = match used.len() {
+            0 => "no placeholders".to_string(),
+            1 => format!("{} only", used.first().unwrap()),
+            _ => {
+                let used: Vec<_> = used.iter().map(|a| a.to_string()).collect();
+                format!("mixed {}", used.join(", "))
+            }
+        };
+        self.styles.add(format!("{name}: {style}"), krate);
+    }
+}
+
+/// Returns the path of a macro being invoked if `before` (the tokens before
+/// a group) ends with one, like `println !` or `$crate :: format !`.
+fn macro_path(before: &[TokenTree]) -> Option<String> {
+    let [rest @ .., TokenTree::Ident(last), TokenTree::Punct(bang)] = before else {
+        return None;
+    };
+    if bang.as_char() != '!' || KEYWORDS.contains(&last.to_string().as_str()) {
+        return None;
+    }
+    let mut segments = vec![last.to_string()];
+    let mut rest = rest;
+    while let [more @ .., TokenTree::Ident(ident), TokenTree::Punct(c1), TokenTree::Punct(c2)] =
+        rest
+    {
+        if c1.as_char() != ':' || c2.as_char() != ':' {
+            break;
+        }
+        segments.push(ident.to_string());
+        rest = more;
+    }
+    // `$name!(..)` invokes a macro passed to a `macro_rules!` macro, and
+    // `$crate` is the crate defining it.
+    if let [.., TokenTree::Punct(dollar)] = rest {
+        if dollar.as_char() == '$' {
+            if segments.last().unwrap() != "crate" {
+                return None;
+            }
+            *segments.last_mut().unwrap() = "$crate".to_string();
+        }
+    }
+    segments.reverse();
+    Some(segments.join("::"))
+}
+
+/// Splits macro arguments at top-level commas.
+fn split_args(tokens: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut args = vec![Vec::new()];
+    for tt in tokens {
+        match tt {
+            TokenTree::Punct(p) if p.as_char() == ',' => args.push(Vec::new()),
+            tt => args.last_mut().unwrap().push(tt),
+        }
+    }
+    args
+}
+
+/// What a placeholder refers to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Argument {
+    /// `{}`
+    Implicit,
+    /// `{0}`
+    Explicit,
+    /// `{name}` with a `name = ...` argument.
+    Named,
+    /// `{name}` capturing a variable in scope.
+    Inline,
+}
+
+impl std::fmt::Display for Argument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Argument::Implicit => "implicit positional",
+            Argument::Explicit => "explicit positional",
+            Argument::Named => "named",
+            Argument::Inline => "inline captured",
+        })
+    }
+}
+
+struct Placeholder {
+    argument: Argument,
+    specs: Vec<&'static str>,
+    trait_name: &'static str,
+}
+
+/// Parses the placeholders of a format string, or returns `None` if it
+/// isn't valid. `named` are the names of `name = ...` arguments.
+fn placeholders(s: &str, named: &[String]) -> Option<Vec<Placeholder>> {
+    let mut found = Vec::new();
+    let mut rest = s;
+    while let Some(i) = rest.find(['{', '}']) {
+        let c = rest.as_bytes()[i];
+        let after = &rest[i + 1..];
+        if after.as_bytes().first() == Some(&c) {
+            // `{{` or `}}`
+            rest = &after[1..];
+        } else if c == b'{' {
+            let end = after.find('}')?;
+            found.push(placeholder(&after[..end], named)?);
+            rest = &after[end + 1..];
+        } else {
+            return None;
+        }
+    }
+    Some(found)
+}
+
+/// Parses what is between `{` and `}`, like `name:>8.2?`.
+fn placeholder(inner: &str, named: &[String]) -> Option<Placeholder> {
+    let (arg, spec) = inner.split_once(':').unwrap_or((inner, ""));
+    let argument = if arg.is_empty() {
+        Argument::Implicit
+    } else if arg.bytes().all(|b| b.is_ascii_digit()) {
+        Argument::Explicit
+    } else if is_identifier(arg) {
+        if named.iter().any(|n| n == arg) {
+            Argument::Named
+        } else {
+            Argument::Inline
+        }
+    } else {
+        return None;
+    };
+
+    // [[fill]align][sign]['#']['0'][width]['.' precision]type
+    let chars: Vec<char> = spec.chars().collect();
+    let is_align = |c: &char| matches!(c, '<' | '^' | '>');
+    let mut specs = Vec::new();
+    let mut i = 0;
+    if chars.get(1).is_some_and(is_align) {
+        specs.push("fill and align");
+        i = 2;
+    } else if chars.first().is_some_and(is_align) {
+        specs.push("align");
+        i = 1;
+    }
+    if matches!(chars.get(i), Some('+' | '-')) {
+        specs.push("sign");
+        i += 1;
+    }
+    if chars.get(i) == Some(&'#') {
+        specs.push("alternate");
+        i += 1;
+    }
+    if chars.get(i) == Some(&'0') && chars.get(i + 1) != Some(&'$') {
+        specs.push("zero padding");
+        i += 1;
+    }
+    if let Some((from_argument, len)) = count(&chars[i..]) {
+        specs.push(if from_argument {
+            "width from argument"
+        } else {
+            "width"
+        });
+        i += len;
+    }
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        if chars.get(i) == Some(&'*') {
+            specs.push("precision from argument");
+            i += 1;
+        } else {
+            let (from_argument, len) = count(&chars[i..])?;
+            specs.push(if from_argument {
+                "precision from argument"
+            } else {
+                "precision"
+            });
+            i += len;
+        }
+    }
+    let ty: String = chars[i..].iter().collect();
+    let trait_name = match ty.as_str() {
+        "" => "Display",
+        "?" => "Debug",
+        "x?" | "X?" => "Debug (hex)",
+        "x" => "LowerHex",
+        "X" => "UpperHex",
+        "o" => "Octal",
+        "b" => "Binary",
+        "e" => "LowerExp",
+        "E" => "UpperExp",
+        "p" => "Pointer",
+        _ => return None,
+    };
+    Some(Placeholder {
+        argument,
+        specs,
+        trait_name,
+    })
+}
+
+/// Parses a width or precision at the start of `chars`: a number, or a
+/// number or name followed by `$` to take it from an argument. Returns
+/// whether it's from an argument, and its length.
+fn count(chars: &[char]) -> Option<(bool, usize)> {
+    let digits = chars.iter().take_while(|c| c.is_ascii_digit()).count();
+    let len = if digits > 0 {
+        digits
+    } else {
+        chars
+            .iter()
+            .take_while(|c| c.is_alphanumeric() || **c == '_')
+            .count()
+    };
+    if len > 0 && chars.get(len) == Some(&'$') {
+        Some((true, len + 1))
+    } else if digits > 0 {
+        Some((false, digits))
+    } else {
+        None
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str, named: &[&str]) -> Option<Vec<(Argument, Vec<&'static str>, &'static str)>> {
+        let named: Vec<_> = named.iter().map(|n| n.to_string()).collect();
+        let found = placeholders(s, &named)?;
+        Some(
+            found
+                .into_iter()
+                .map(|p| (p.argument, p.specs, p.trait_name))
+                .collect(),
+        )
+    }
+
+    fn one(s: &str) -> (Argument, Vec<&'static str>, &'static str) {
+        let mut found = parse(s, &[]).unwrap();
+        assert_eq!(found.len(), 1, "{s}");
+        found.pop().unwrap()
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!(parse("{{}}", &[]), Some(vec![]));
+        assert_eq!(parse("{{{}}}", &[]).unwrap().len(), 1);
+        assert_eq!(parse("a {{x}} b", &[]), Some(vec![]));
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(one("{}").0, Argument::Implicit);
+        assert_eq!(one("{0}").0, Argument::Explicit);
+        assert_eq!(one("{x}").0, Argument::Inline);
+        assert_eq!(parse("{x}", &["x"]).unwrap()[0].0, Argument::Named);
+        assert_eq!(parse("{x}", &["y"]).unwrap()[0].0, Argument::Inline);
+        assert_eq!(one("{_x:?}").0, Argument::Inline);
+    }
+
+    #[test]
+    fn specs() {
+        assert_eq!(
+            one("{:>8.2?}"),
+            (
+                Argument::Implicit,
+                vec!["align", "width", "precision"],
+                "Debug"
+            )
+        );
+        assert_eq!(
+            one("{:<<5}"),
+            (
+                Argument::Implicit,
+                vec!["fill and align", "width"],
+                "Display"
+            )
+        );
+        assert_eq!(
+            one("{0:1$}"),
+            (Argument::Explicit, vec!["width from argument"], "Display")
+        );
+        assert_eq!(
+            one("{:.*}"),
+            (
+                Argument::Implicit,
+                vec!["precision from argument"],
+                "Display"
+            )
+        );
+        assert_eq!(
+            one("{:.prec$}"),
+            (
+                Argument::Implicit,
+                vec!["precision from argument"],
+                "Display"
+            )
+        );
+        // `0$` is a width taken from argument 0, not zero padding.
+        assert_eq!(
+            one("{:0$}"),
+            (Argument::Implicit, vec!["width from argument"], "Display")
+        );
+        assert_eq!(
+            one("{:08.3}"),
+            (
+                Argument::Implicit,
+                vec!["zero padding", "width", "precision"],
+                "Display"
+            )
+        );
+        assert_eq!(
+            one("{:#x}"),
+            (Argument::Implicit, vec!["alternate"], "LowerHex")
+        );
+        assert_eq!(one("{:+e}"), (Argument::Implicit, vec!["sign"], "LowerExp"));
+        assert_eq!(
+            one("{x:-^#010X}"),
+            (
+                Argument::Inline,
+                vec!["fill and align", "alternate", "zero padding", "width"],
+                "UpperHex"
+            )
+        );
+    }
+
+    #[test]
+    fn traits() {
+        for (spec, trait_name) in [
+            ("{:?}", "Debug"),
+            ("{:x?}", "Debug (hex)"),
+            ("{:X?}", "Debug (hex)"),
+            ("{:o}", "Octal"),
+            ("{:b}", "Binary"),
+            ("{:E}", "UpperExp"),
+            ("{:p}", "Pointer"),
+        ] {
+            assert_eq!(one(spec).2, trait_name, "{spec}");
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        for s in [
+            "{", "}", "a } b", "{:q}", "{x y}", "{:.}", "{:8.}", "{-1}", "{:?x}",
+        ] {
+            assert!(parse(s, &[]).is_none(), "{s}");
+        }
+    }
+
+    fn census(src: &str, edition: Option<&str>) -> Census {
+        let census = Census {
+            invocations: Tally::new(5),
+            delimiters: Tally::new(5),
+            arguments: Tally::new(5),
+            specs: Tally::new(5),
+            traits: Tally::new(5),
+            styles: Tally::new(5),
+        };
+        census.scan("c-1.0.0", src.parse().unwrap(), edition);
+        census
+    }
+
+    fn sorted(tally: &Tally) -> Vec<String> {
+        let mut categories = tally.categories();
+        categories.sort();
+        categories
+    }
+
+    #[test]
+    fn keywords_are_not_macros() {
+        let census = census("fn f(a: bool) -> bool { if !(a) {} return !(a); }", None);
+        assert!(census.invocations.categories().is_empty());
+    }
+
+    #[test]
+    fn paths() {
+        let census = census(
+            "macro_rules! m { ($name:ident) => { $crate::inner::helper!(1); $name!(2); std::println![3]; } }",
+            None,
+        );
+        assert_eq!(
+            sorted(&census.invocations),
+            ["$crate::inner::helper", "std::println"]
+        );
+        assert_eq!(census.delimiters.count("std::println![..]"), 1);
+    }
+
+    #[test]
+    fn log_target() {
+        let census = census(
+            r#"fn f() { log::info!(target: "net", "{} bytes", n); warn!(target: "x", "{n}"); }"#,
+            Some("2021"),
+        );
+        assert_eq!(
+            sorted(&census.styles),
+            [
+                "info: implicit positional only",
+                "warn: inline captured only"
+            ]
+        );
+    }
+
+    #[test]
+    fn lone_messages_before_2021() {
+        let src =
+            r#"fn f() { panic!("{}"); assert!(c, "{x}"); unreachable!("{}", x); todo!("{}"); }"#;
+        assert_eq!(
+            sorted(&census(src, Some("2018")).styles),
+            [
+                "todo: implicit positional only",
+                "unreachable: implicit positional only"
+            ]
+        );
+        assert_eq!(
+            sorted(&census(src, Some("2021")).styles),
+            [
+                "assert: inline captured only",
+                "panic: implicit positional only",
+                "todo: implicit positional only",
+                "unreachable: implicit positional only"
+            ]
+        );
+    }
+}
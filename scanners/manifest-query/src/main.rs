@@ -0,0 +1,255 @@
+//! Builds a SQLite database of crate manifests, and runs queries on it.
+//!
+//! Building reads the `Cargo.toml` of every selected `.crate` file once.
+//! After that, questions about manifests are SQL queries that take seconds
+//! instead of a new scanner and another pass over every crate:
+//!
+//! ```text
+//! manifest-query build CRATES_PATH manifests.db --latest
+//! manifest-query query manifests.db underscore-bins
+//! manifest-query query manifests.db "SELECT name, version FROM crates WHERE links IS NOT NULL"
+//! ```
+//!
+//! The tables are:
+//!
+//! - `crates`: `id`, `name`, `version`, `edition`, `rust_version`, `links`,
+//!   and `manifest` with the whole manifest as JSON for SQLite's JSON
+//!   functions.
+//! - `targets`: `crate_id`, `kind` (`lib`, `bin`, `example`, `test`,
+//!   `bench`, `custom-build`), `name`, `path`, `edition` (only if set on the
+//!   target), `required_features` (JSON), and `inferred` for targets cargo
+//!   discovers automatically.
+//! - `dependencies`: `crate_id`, `name`, `package` (if renamed), `req`,
+//!   `kind` (`normal`, `dev`, `build`), `target`, `optional`,
+//!   `default_features`, `features` (JSON).
+//! - `features`: `crate_id`, `name`, `value` (JSON).
+
+use anyhow::{bail, Result};
+use core::sync::atomic::{AtomicU32, Ordering};
+use crates_scanner::manifest::Manifest;
+use crates_scanner::{CrateId, Selection, Versions};
+use rayon::prelude::*;
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::sync::Mutex;
+
+const USAGE: &str = "\
+usage: manifest-query build CRATES_PATH DB_PATH [selection options]
+       manifest-query query DB_PATH (SQL | QUERY_NAME)";
+
+/// Named queries for common questions.
+const QUERIES: &[(&str, &str)] = &[
+    (
+        "underscore-bins",
+        "SELECT c.name, c.version, t.name FROM targets t JOIN crates c ON c.id = t.crate_id \
+         WHERE t.kind = 'bin' AND t.name LIKE '%\\_%' ESCAPE '\\'",
+    ),
+    (
+        "lib-edition-mismatch",
+        "SELECT c.name, c.version, c.edition, t.edition FROM targets t \
+         JOIN crates c ON c.id = t.crate_id \
+         WHERE t.kind = 'lib' AND t.edition IS NOT NULL AND t.edition != c.edition",
+    ),
+    (
+        "renamed-deps",
+        "SELECT c.name, c.version, d.name, d.package FROM dependencies d \
+         JOIN crates c ON c.id = d.crate_id WHERE d.package IS NOT NULL",
+    ),
+];
+
+const SCHEMA: &str = "
+CREATE TABLE crates (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    version TEXT NOT NULL,
+    edition TEXT NOT NULL,
+    rust_version TEXT,
+    links TEXT,
+    manifest TEXT NOT NULL
+);
+CREATE TABLE targets (
+    crate_id INTEGER NOT NULL REFERENCES crates(id),
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    path TEXT NOT NULL,
+    edition TEXT,
+    required_features TEXT NOT NULL,
+    inferred INTEGER NOT NULL
+);
+CREATE TABLE dependencies (
+    crate_id INTEGER NOT NULL REFERENCES crates(id),
+    name TEXT NOT NULL,
+    package TEXT,
+    req TEXT,
+    kind TEXT NOT NULL,
+    target TEXT,
+    optional INTEGER NOT NULL,
+    default_features INTEGER NOT NULL,
+    features TEXT NOT NULL
+);
+CREATE TABLE features (
+    crate_id INTEGER NOT NULL REFERENCES crates(id),
+    name TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+CREATE INDEX crates_name ON crates(name);
+CREATE INDEX targets_crate ON targets(crate_id);
+CREATE INDEX dependencies_crate ON dependencies(crate_id);
+CREATE INDEX dependencies_name ON dependencies(name);
+CREATE INDEX features_crate ON features(crate_id);
+";
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let result = match args.first().map(|s| s.as_str()) {
+        Some("build") => build(args[1..].to_vec()),
+        Some("query") => match &args[1..] {
+            [db_path, query] => run_query(Path::new(db_path), query),
+            _ => {
+                eprintln!("{USAGE}");
+                std::process::exit(1);
+            }
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("error: {e:?}");
+        std::process::exit(1);
+    }
+}
+
+fn build(args: Vec<String>) -> Result<()> {
+    let (selection, args) = match Selection::from_args(Versions::Latest, args) {
+        Ok(r) => r,
+        Err(e) => bail!("{e}\n{USAGE}\n{}", crates_scanner::selection::USAGE),
+    };
+    if let Some(arg) = args.iter().find(|arg| arg.starts_with("--")) {
+        bail!("unexpected argument `{arg}`\n{USAGE}");
+    }
+    let [crates_path, db_path] = match <[String; 2]>::try_from(args) {
+        Ok(paths) => paths,
+        Err(_) => bail!("expected CRATES_PATH and DB_PATH\n{USAGE}"),
+    };
+    let db_path = Path::new(&db_path);
+    if db_path.exists() {
+        std::fs::remove_file(db_path)?;
+    }
+    let conn = Connection::open(db_path)?;
+    conn.execute_batch(SCHEMA)?;
+    conn.execute_batch("BEGIN")?;
+    let conn = Mutex::new(conn);
+
+    let paths = selection.collect(Path::new(&crates_path));
+    eprintln!("reading {} manifests", paths.len());
+    let errors = AtomicU32::new(0);
+    paths.par_iter().for_each(|path| {
+        let id = CrateId::from_crate_path(path).unwrap();
+        let result = Manifest::from_crate_file(path)
+            .and_then(|manifest| insert(&conn.lock().unwrap(), &id, &manifest));
+        if let Err(e) = result {
+            errors.fetch_add(1, Ordering::SeqCst);
+            eprintln!("error: {id}: {e:?}");
+        }
+    });
+    let conn = conn.into_inner().unwrap();
+    conn.execute_batch("COMMIT")?;
+    println!(
+        "errors: {}\n\
+        total: {}",
+        errors.load(Ordering::SeqCst),
+        paths.len()
+    );
+    Ok(())
+}
+
+fn insert(conn: &Connection, id: &CrateId, manifest: &Manifest) -> Result<()> {
+    conn.execute(
+        "INSERT INTO crates (name, version, edition, rust_version, links, manifest) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            id.name,
+            id.version.to_string(),
+            manifest.edition,
+            manifest.rust_version,
+            manifest.links,
+            serde_json::to_string(&manifest.raw)?,
+        ],
+    )?;
+    let crate_id = conn.last_insert_rowid();
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO targets \
+         (crate_id, kind, name, path, edition, required_features, inferred) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?;
+    for t in &manifest.targets {
+        stmt.execute(params![
+            crate_id,
+            t.kind.to_string(),
+            t.name,
+            t.path.to_string_lossy(),
+            t.edition,
+            serde_json::to_string(&t.required_features)?,
+            t.inferred,
+        ])?;
+    }
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO dependencies \
+         (crate_id, name, package, req, kind, target, optional, default_features, features) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+    for d in &manifest.dependencies {
+        stmt.execute(params![
+            crate_id,
+            d.name,
+            d.package,
+            d.req,
+            d.kind.to_string(),
+            d.target,
+            d.optional,
+            d.default_features,
+            serde_json::to_string(&d.features)?,
+        ])?;
+    }
+    let mut stmt =
+        conn.prepare_cached("INSERT INTO features (crate_id, name, value) VALUES (?1, ?2, ?3)")?;
+    for (name, value) in &manifest.features {
+        stmt.execute(params![crate_id, name, serde_json::to_string(value)?])?;
+    }
+    Ok(())
+}
+
+/// Runs a query and prints the results as tab-separated lines, with the
+/// column names first.
+fn run_query(db_path: &Path, query: &str) -> Result<()> {
+    if !db_path.exists() {
+        bail!("{db_path:?} does not exist, run `manifest-query build` first");
+    }
+    let sql = QUERIES
+        .iter()
+        .find(|(name, _)| *name == query)
+        .map_or(query, |(_, sql)| sql);
+    let conn = Connection::open(db_path)?;
+    let mut stmt = conn.prepare(sql)?;
+    println!("{}", stmt.column_names().join("\t"));
+    let columns = stmt.column_count();
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let values: Vec<String> = (0..columns)
+            .map(|i| {
+                Ok(match row.get_ref(i)? {
+                    rusqlite::types::ValueRef::Null => "NULL".to_string(),
+                    rusqlite::types::ValueRef::Integer(i) => i.to_string(),
+                    rusqlite::types::ValueRef::Real(f) => f.to_string(),
+                    rusqlite::types::ValueRef::Text(t) | rusqlite::types::ValueRef::Blob(t) => {
+                        String::from_utf8_lossy(t).into_owned()
+                    }
+                })
+            })
+            .collect::<Result<_>>()?;
+        println!("{}", values.join("\t"));
+    }
+    Ok(())
+}
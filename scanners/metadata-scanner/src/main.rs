@@ -1,43 +1,170 @@
 //! Uses cargo_metadata on all crates.
+//!
+//! The package information for every crate is written to `metadata.jsonl`
+//! (or `--output FILE`), one JSON object per crate, so questions about the
+//! ecosystem can be answered from that file instead of running cargo over
+//! every crate again. For example, to find binaries with an underscore in
+//! their name:
+//!
+//! ```text
+//! jq -r 'select(.targets[] | (.kind == ["bin"]) and (.name | contains("_"))) | .crate' metadata.jsonl
+//! ```
 
-use anyhow::bail;
-use cargo_metadata::*;
+use anyhow::{bail, format_err, Result};
+use cargo_metadata::{MetadataCommand, Package};
+use crates_scanner::cargo::{CargoRunner, Workspace};
+use crates_scanner::results::Sink;
+use serde_json::{json, Value};
 use std::path::Path;
 
+const USAGE: &str = "usage: metadata-scanner SRC_PATH [--output FILE] [cargo runner options]";
+
+/// Settings from `[package]` that cargo metadata doesn't report.
+const AUTO_SETTINGS: &[&str] = &[
+    "autolib",
+    "autobins",
+    "autoexamples",
+    "autotests",
+    "autobenches",
+];
+
 fn main() {
-    let src_path = std::env::args()
-        .skip(1)
-        .next()
-        .expect("first argument must be a path to the extracted source directory");
-
-    crates_scanner::scan_uncompressed(Path::new(&src_path), |path| {
-        let meta = match MetadataCommand::new()
-            .manifest_path(path.join("Cargo.toml"))
-            .no_deps()
-            .exec()
-        {
-            Ok(meta) => meta,
-            Err(e) => {
-                bail!("could not run metadata for {path:?}: {e}");
-            }
+    let (runner, src_path, output) = match parse_args() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("error: {e}\n{USAGE}\n{}", crates_scanner::cargo::USAGE);
+            std::process::exit(1);
+        }
+    };
+    let results = Sink::create(&output).unwrap();
+
+    let scanned = crates_scanner::scan_uncompressed(Path::new(&src_path), |path| {
+        let ws = runner.prepare(path)?;
+        let output = runner.run(&ws, &["metadata", "--format-version=1", "--no-deps"])?;
+        output.check_timeout()?;
+        if !output.success() {
+            bail!("could not run metadata for {path:?}: {}", output.stderr);
+        }
+        let meta = MetadataCommand::parse(&output.stdout)?;
+        // A published manifest can still declare workspace members, which
+        // are reported too, so pick the crate by its manifest.
+        let manifest_path = ws.path().join("Cargo.toml").canonicalize()?;
+        let package = meta
+            .packages
+            .iter()
+            .find(|p| p.manifest_path.canonicalize().ok().as_ref() == Some(&manifest_path));
+        let Some(package) = package else {
+            bail!("no package in the metadata of {path:?} is the crate itself");
         };
-        assert_eq!(meta.packages.len(), 1);
-        for target in &meta.packages[0].targets {
-            if target.kind.iter().any(|t| *t == TargetKind::Bin) {
-                if target.name.contains('_') {
-                    println!("found `{}` in {}", target.name, path.display());
-                }
+        let krate = path.file_name().unwrap().to_str().unwrap();
+        results.write(&package_record(krate, &ws, package)?)
+    });
+    if let Err(e) = scanned {
+        eprintln!("error: {e:#}");
+        std::process::exit(1);
+    }
+    results.flush().unwrap();
+}
+
+fn parse_args() -> Result<(CargoRunner, String, String)> {
+    let (runner, args) = CargoRunner::from_args("cargo", std::env::args().skip(1))?;
+    let mut output = "metadata.jsonl".to_string();
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => {
+                output = args
+                    .next()
+                    .ok_or_else(|| format_err!("`--output` requires a value"))?;
             }
+            s if s.starts_with("--") => bail!("unexpected argument `{s}`"),
+            _ => positional.push(arg),
         }
-        // for dep in &meta.packages[0].dependencies {
-        //     if dep.features.iter().any(|f| f.starts_with('_')) {
-        //         println!(
-        //             "{:?} depends on {} with features: {:?}",
-        //             path, dep.name, dep.features
-        //         );
-        //     }
-        // }
-
-        Ok(())
-    });
+    }
+    let [src_path] =
+        <[String; 1]>::try_from(positional).map_err(|_| format_err!("expected SRC_PATH"))?;
+    Ok((runner, src_path, output))
+}
+
+/// Builds the dataset record for one package.
+///
+/// Paths are relative to the package root, and fields that are almost
+/// always empty are left out when they are.
+fn package_record(krate: &str, ws: &Workspace, package: &Package) -> Result<Value> {
+    let targets: Vec<_> = package
+        .targets
+        .iter()
+        .map(|target| {
+            let src_path = target
+                .src_path
+                .strip_prefix(ws.path())
+                .unwrap_or(&target.src_path);
+            let mut t = json!({
+                "name": target.name,
+                "kind": target.kind,
+                "src_path": src_path,
+            });
+            if !target.required_features.is_empty() {
+                t["required_features"] = json!(target.required_features);
+            }
+            if target.edition != package.edition {
+                t["edition"] = json!(target.edition);
+            }
+            t
+        })
+        .collect();
+    let dependencies: Vec<_> = package
+        .dependencies
+        .iter()
+        .map(|dep| {
+            let mut d = json!({
+                "name": dep.name,
+                "req": dep.req.to_string(),
+                "kind": dep.kind.to_string(),
+            });
+            if dep.optional {
+                d["optional"] = json!(true);
+            }
+            if !dep.uses_default_features {
+                d["default_features"] = json!(false);
+            }
+            if !dep.features.is_empty() {
+                d["features"] = json!(dep.features);
+            }
+            if let Some(target) = &dep.target {
+                d["target"] = json!(target.to_string());
+            }
+            if let Some(rename) = &dep.rename {
+                d["rename"] = json!(rename);
+            }
+            if dep.path.is_some() {
+                d["path"] = json!(true);
+            }
+            d
+        })
+        .collect();
+
+    let manifest: toml::Value =
+        toml::from_str(&std::fs::read_to_string(ws.path().join("Cargo.toml"))?)?;
+    let auto: serde_json::Map<_, _> = AUTO_SETTINGS
+        .iter()
+        .filter_map(|key| {
+            let value = manifest.get("package")?.get(key)?.as_bool()?;
+            Some((key.to_string(), json!(value)))
+        })
+        .collect();
+
+    Ok(json!({
+        "crate": krate,
+        "name": package.name,
+        "version": package.version.to_string(),
+        "edition": package.edition,
+        "rust_version": package.rust_version.as_ref().map(|v| v.to_string()),
+        "links": package.links,
+        "features": package.features,
+        "auto": auto,
+        "targets": targets,
+        "dependencies": dependencies,
+    }))
 }
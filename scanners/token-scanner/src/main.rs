@@ -1,52 +1,235 @@
 //! Scans rust source using tokens.
+//!
+//! By default this finds string literals where a `\` line continuation is
+//! followed by an empty line. With `--pattern`, it finds matches of token
+//! patterns instead (see `crates_scanner::pattern`), printing each one and
+//! counting them by what they captured. With `--literal`, it finds string,
+//! character and byte literals whose decoded value matches a regex, so
+//! escapes and raw strings don't hide anything:
+//!
+//! ```text
+//! token-scanner CRATES_PATH --kinds lib --pattern '#[cfg(feature = $lit)]'
+//! token-scanner CRATES_PATH --literal '^https?://'
+//! ```
 
-use anyhow::Result;
+use anyhow::{bail, format_err, Result};
+use crates_scanner::findings::{Finding, Span};
+use crates_scanner::literal::{Literal, LiteralKind};
+use crates_scanner::modules::{is_package_file, parse_kinds, CrateFiles, FileKind};
+use crates_scanner::pattern::Pattern;
+use crates_scanner::results::Tally;
+use crates_scanner::CrateSources;
 use proc_macro2::{TokenStream, TokenTree};
+use regex::Regex;
+use std::collections::HashSet;
 use std::path::Path;
 use std::str::FromStr;
 
-fn main() {
-    let crates_path = std::env::args()
-        .skip(1)
-        .next()
-        .expect("first argument must be a path to the crates directory");
-
-    crates_scanner::scan_compressed(
-        Path::new(&crates_path),
-        crates_scanner::Versions::All,
-        |path| path.extension().map_or(false, |ext| ext == "rs"),
-        scan_rust,
-    );
-}
+const USAGE: &str = "\
+usage: token-scanner [--extracted] PATH [--kinds KINDS]
+                     [--pattern PATTERN]... [--literal REGEX]...
 
-static CONT_RE: once_cell::sync::OnceCell<regex::Regex> = once_cell::sync::OnceCell::new();
+PATH is the crates directory, or the extracted source directory with
+--extracted. --kinds only scans files compiled as one of KINDS, like
+`lib,bin`. --literal finds string, character and byte literals whose
+decoded value matches REGEX. --pattern and --literal may be repeated.";
+
+struct Args {
+    path: String,
+    extracted: bool,
+    kinds: Option<HashSet<FileKind>>,
+    patterns: Vec<Pattern>,
+    literals: Vec<Regex>,
+}
 
-fn scan_rust(path: &Path, contents: &str) -> Result<()> {
-    let tokens = match proc_macro2::TokenStream::from_str(contents) {
-        Ok(t) => t,
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
         Err(e) => {
-            eprintln!("failed to parse {path:?}: {e}");
-            return Ok(());
+            eprintln!("error: {e}\n{USAGE}");
+            std::process::exit(1);
         }
     };
-    scan(path, tokens)
+
+    let tallies: Vec<_> = args.patterns.iter().map(|_| Tally::new(5)).collect();
+    let literal_tallies: Vec<_> = args.literals.iter().map(|_| Tally::new(5)).collect();
+    let scan_one = |files: Option<&CrateFiles>, krate: &str, path: &Path, contents: &str| {
+        if let Some(kinds) = &args.kinds {
+            let kind = files.and_then(|files| files.kind(path));
+            if !kind.is_some_and(|kind| kinds.contains(&kind)) {
+                return Ok(());
+            }
+        }
+        let tokens = match TokenStream::from_str(contents) {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("failed to parse {path:?}: {e}");
+                return Ok(());
+            }
+        };
+        if args.patterns.is_empty() && args.literals.is_empty() {
+            return scan_rust(path, contents, tokens);
+        }
+        scan_patterns(&args.patterns, &tallies, krate, path, contents, &tokens);
+        scan_literals(
+            &args.literals,
+            &literal_tallies,
+            krate,
+            path,
+            contents,
+            tokens,
+        );
+        Ok(())
+    };
+    let scan = |krate: &CrateSources| {
+        let files = args.kinds.is_some().then(|| CrateFiles::new(krate));
+        let name = crates_scanner::crate_name(&krate.path);
+        for (path, contents) in &krate.sources {
+            if path.extension().is_some_and(|ext| ext == "rs") {
+                scan_one(files.as_ref(), &name, path, contents)?;
+            }
+        }
+        Ok(())
+    };
+    let filter = is_package_file;
+    if args.extracted {
+        if let Err(e) =
+            crates_scanner::scan_uncompressed_crates(Path::new(&args.path), filter, scan)
+        {
+            eprintln!("error: {e:#}");
+            std::process::exit(1);
+        }
+    } else {
+        crates_scanner::scan_compressed_crates(
+            Path::new(&args.path),
+            crates_scanner::Versions::All,
+            filter,
+            scan,
+        );
+    }
+    for (pattern, tally) in args.patterns.iter().zip(&tallies) {
+        tally.print(&format!("matches of `{pattern}`"));
+    }
+    for (re, tally) in args.literals.iter().zip(&literal_tallies) {
+        tally.print(&format!("literals matching `{re}`"));
+    }
 }
 
-fn scan(path: &Path, tokens: TokenStream) -> Result<()> {
+fn parse_args() -> Result<Args> {
+    let mut extracted = false;
+    let mut kinds = None;
+    let mut patterns = Vec::new();
+    let mut literals = Vec::new();
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format_err!("`{arg}` requires a value"))
+        };
+        match arg.as_str() {
+            "--extracted" => extracted = true,
+            "--kinds" => kinds = Some(parse_kinds(&value()?)?),
+            "--pattern" => patterns.push(value()?.parse()?),
+            "--literal" => literals.push(Regex::new(&value()?)?),
+            s if s.starts_with("--") => bail!("unexpected argument `{s}`"),
+            _ => positional.push(arg),
+        }
+    }
+    let [path] = <[String; 1]>::try_from(positional).map_err(|_| format_err!("expected PATH"))?;
+    Ok(Args {
+        path,
+        extracted,
+        kinds,
+        patterns,
+        literals,
+    })
+}
+
+/// Prints every match of `patterns` and counts it under what it captured,
+/// or the matched source if it captured nothing.
+fn scan_patterns(
+    patterns: &[Pattern],
+    tallies: &[Tally],
+    krate: &str,
+    path: &Path,
+    contents: &str,
+    tokens: &TokenStream,
+) {
+    for (pattern, tally) in patterns.iter().zip(tallies) {
+        for m in pattern.find_in(tokens.clone()) {
+            let captures: Vec<_> = m
+                .captures
+                .iter()
+                .map(|c| format!("${} = {}", c.name, c.text))
+                .collect();
+            let category = if captures.is_empty() {
+                contents[m.span.byte_range(contents)].to_string()
+            } else {
+                captures.join(", ")
+            };
+            let finding = Finding::new(path, m.span, format!("`{pattern}`: {category}"));
+            eprintln!("{}", finding.render(contents));
+            tally.add(category, krate);
+        }
+    }
+}
+
+static CONT_RE: once_cell::sync::OnceCell<regex::Regex> = once_cell::sync::OnceCell::new();
+
+fn scan_rust(path: &Path, contents: &str, tokens: TokenStream) -> Result<()> {
     let cont_re = CONT_RE.get_or_init(|| regex::Regex::new("\\\\\n *\n").unwrap());
+    literals(tokens, &mut |lit| {
+        // Continuations are only in string literals with escapes, and are
+        // gone from the decoded value, so this looks at the raw text.
+        let is_string = matches!(
+            lit.kind,
+            LiteralKind::Str | LiteralKind::ByteStr | LiteralKind::CStr
+        );
+        if let Some(m) = cont_re.find(&lit.raw).filter(|_| is_string) {
+            let start = lit.span.start.offset(contents);
+            let span = Span::from_offsets(contents, start + m.start(), start + m.end());
+            let finding = Finding::new(path, span, "string continuation followed by an empty line");
+            eprintln!("{}", finding.render(contents));
+        }
+    });
+    Ok(())
+}
+
+/// Prints every string, character or byte literal whose decoded value
+/// matches one of `regexes`, and counts it under its kind and value.
+fn scan_literals(
+    regexes: &[Regex],
+    tallies: &[Tally],
+    krate: &str,
+    path: &Path,
+    contents: &str,
+    tokens: TokenStream,
+) {
+    literals(tokens, &mut |lit| {
+        let Some(text) = lit.text() else { return };
+        for (re, tally) in regexes.iter().zip(tallies) {
+            if re.is_match(&text) {
+                let category = format!("{} {text:?}", lit.kind);
+                let finding = Finding::new(path, lit.span, format!("`{re}`: {category}"));
+                eprintln!("{}", finding.render(contents));
+                tally.add(category, krate);
+            }
+        }
+    });
+}
+
+/// Calls `f` with every literal in `tokens`, decoded.
+fn literals(tokens: TokenStream, f: &mut impl FnMut(Literal)) {
     for tt in tokens {
         match tt {
-            TokenTree::Group(g) => scan(path, g.stream())?,
+            TokenTree::Group(g) => literals(g.stream(), f),
             TokenTree::Literal(l) => {
-                let s = l.to_string();
-                if s.starts_with('"') || s.starts_with("b\"") {
-                    if let Some(m) = cont_re.find(&s) {
-                        eprintln!("found: {:?}: {:?}", path, m.as_str());
-                    }
+                if let Some(lit) = Literal::new(&l) {
+                    f(lit);
                 }
             }
             _ => {}
         }
     }
-    Ok(())
 }
@@ -0,0 +1,205 @@
+//! Checks that TOML files survive being parsed and written back.
+//!
+//! Every `Cargo.toml`, `*.toml` and `Cargo.lock` is checked in three ways:
+//!
+//! - `toml`: parse into a table, serialize it, parse the result, and
+//!   compare the two values. Formatting is lost, but the values shouldn't
+//!   be.
+//! - `toml_edit`: parse into a document and print it again, which should
+//!   give back the same bytes.
+//! - `toml_edit` edit: add a dependency to `[dependencies]` the way
+//!   `cargo add` does, check that only that key changed, then remove it
+//!   the way `cargo remove` does and check the bytes are back to the
+//!   original.
+
+use anyhow::{bail, Result};
+use crates_scanner::parser_diff::{differences, Difference, DifferenceKind, NormalizedValue};
+use crates_scanner::results::Tally;
+use crates_scanner::{Selection, Versions};
+use std::path::Path;
+
+const USAGE: &str = "usage: toml-roundtrip CRATES_PATH [selection options]";
+
+type CheckFn = fn(&str) -> Result<Check>;
+
+const CHECKS: &[(&str, CheckFn)] = &[
+    ("toml", serde_roundtrip),
+    ("toml_edit", edit_roundtrip),
+    ("toml_edit edit", add_remove_dependency),
+];
+
+/// The dependency added and removed by the edit check.
+const EDIT_KEY: &str = "crates-scanner-roundtrip";
+
+fn main() {
+    let (selection, args) = match Selection::from_args(Versions::All, std::env::args().skip(1)) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("error: {e}\n{USAGE}\n{}", crates_scanner::selection::USAGE);
+            std::process::exit(1);
+        }
+    };
+    if let Some(arg) = args.iter().find(|arg| arg.starts_with("--")) {
+        eprintln!("unexpected argument `{arg}`\n{USAGE}");
+        std::process::exit(1);
+    }
+    let [crates_path] = match <[String; 1]>::try_from(args) {
+        Ok(paths) => paths,
+        Err(_) => {
+            eprintln!("{USAGE}");
+            std::process::exit(1);
+        }
+    };
+
+    let failures = Tally::new(5);
+    let parse_errors = Tally::new(5);
+    crates_scanner::scan_compressed(
+        Path::new(&crates_path),
+        selection,
+        |path| {
+            path.extension().is_some_and(|e| e == "toml")
+                || path.file_name().is_some_and(|n| n == "Cargo.lock")
+        },
+        |crate_path, entry_path, contents| {
+            let krate = crates_scanner::crate_name(crate_path);
+            for &(name, check) in CHECKS {
+                match check(contents) {
+                    Ok(Check::Ok) => {}
+                    Ok(Check::ParseError) => parse_errors.add(name, &krate),
+                    Ok(Check::Failed(category, details)) => {
+                        eprintln!("{krate} {entry_path:?}: {name}: {category}\n{details}");
+                        failures.add(format!("{name}: {category}"), &krate);
+                    }
+                    Err(e) => {
+                        eprintln!("{krate} {entry_path:?}: {name}: {e}");
+                        failures.add(format!("{name}: error"), &krate);
+                    }
+                }
+            }
+            Ok(())
+        },
+    );
+    failures.print("round-trip failures");
+    parse_errors.print("files that don't parse (skipped)");
+}
+
+/// The result of one check on one file.
+enum Check {
+    Ok,
+    /// The file didn't parse in the first place, so there is nothing to
+    /// round-trip.
+    ParseError,
+    /// The round trip lost something, with a category and details.
+    Failed(String, String),
+}
+
+/// Parses with `toml`, serializes, and parses again.
+fn serde_roundtrip(contents: &str) -> Result<Check> {
+    let Ok(table) = toml::from_str::<toml::Table>(contents) else {
+        return Ok(Check::ParseError);
+    };
+    let serialized = match toml::to_string(&table) {
+        Ok(s) => s,
+        Err(e) => return Ok(Check::Failed("serialize error".to_string(), e.to_string())),
+    };
+    let reparsed = match toml::from_str::<NormalizedValue>(&serialized) {
+        Ok(v) => v,
+        Err(e) => {
+            let details = format!("{e}\nserialized:\n{serialized}");
+            return Ok(Check::Failed("reparse error".to_string(), details));
+        }
+    };
+    let original: NormalizedValue = toml::from_str(contents)?;
+    Ok(lossy(&original, &reparsed))
+}
+
+/// Parses with `toml_edit` and prints the document again.
+fn edit_roundtrip(contents: &str) -> Result<Check> {
+    let Ok(doc) = contents.parse::<toml_edit::DocumentMut>() else {
+        return Ok(Check::ParseError);
+    };
+    Ok(bytes_changed(contents, &doc.to_string()))
+}
+
+/// Adds a dependency to an existing `[dependencies]` table and removes it
+/// again.
+fn add_remove_dependency(contents: &str) -> Result<Check> {
+    let Ok(mut doc) = contents.parse::<toml_edit::DocumentMut>() else {
+        return Ok(Check::ParseError);
+    };
+    let Some(deps) = doc
+        .get_mut("dependencies")
+        .and_then(|d| d.as_table_like_mut())
+    else {
+        return Ok(Check::Ok);
+    };
+    if deps.contains_key(EDIT_KEY) {
+        bail!("already has a `{EDIT_KEY}` dependency");
+    }
+    deps.insert(EDIT_KEY, toml_edit::value("1.0"));
+    let added = doc.to_string();
+
+    // Only the new dependency may differ.
+    let original: NormalizedValue = toml::from_str(contents)?;
+    let edited = match toml::from_str::<NormalizedValue>(&added) {
+        Ok(v) => v,
+        Err(e) => {
+            let details = format!("{e}\nedited:\n{added}");
+            return Ok(Check::Failed("edit doesn't parse".to_string(), details));
+        }
+    };
+    let expected = format!("dependencies.{EDIT_KEY}");
+    let unexpected: Vec<_> = differences(&original, &edited)
+        .into_iter()
+        .filter(|d| d.path != expected || d.kind != DifferenceKind::KeyMissing)
+        .collect();
+    if !unexpected.is_empty() {
+        return Ok(Check::Failed(
+            "edit changed other values".to_string(),
+            list(&unexpected),
+        ));
+    }
+
+    let deps = doc["dependencies"].as_table_like_mut().unwrap();
+    deps.remove(EDIT_KEY);
+    match bytes_changed(contents, &doc.to_string()) {
+        Check::Failed(_, details) => Ok(Check::Failed(
+            "remove didn't restore bytes".to_string(),
+            details,
+        )),
+        check => Ok(check),
+    }
+}
+
+/// Fails with the differences between `original` and `reparsed`, grouped
+/// by the kind of the first one.
+fn lossy(original: &NormalizedValue, reparsed: &NormalizedValue) -> Check {
+    let differences = differences(original, reparsed);
+    match differences.first() {
+        None => Check::Ok,
+        Some(first) => Check::Failed(format!("lossy ({})", first.kind), list(&differences)),
+    }
+}
+
+/// Fails with the first differing line if `new` isn't byte for byte the
+/// same as `old`.
+fn bytes_changed(old: &str, new: &str) -> Check {
+    if old == new {
+        return Check::Ok;
+    }
+    let mut old_lines = old.split_inclusive('\n');
+    let mut new_lines = new.split_inclusive('\n');
+    let mut line = 1;
+    let details = loop {
+        match (old_lines.next(), new_lines.next()) {
+            (Some(a), Some(b)) if a == b => line += 1,
+            (a, b) => break format!("line {line}:\n    old: {a:?}\n    new: {b:?}"),
+        }
+    };
+    Check::Failed("bytes changed".to_string(), details)
+}
+
+fn list(differences: &[Difference]) -> String {
+    let lines: Vec<_> = differences.iter().map(|d| format!("    {d}")).collect();
+    lines.join("\n")
+}
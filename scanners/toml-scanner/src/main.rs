@@ -1,57 +1,98 @@
 //! Scanning `Cargo.toml` with just a toml parser.
+//!
+//! Disagreements between toml versions are printed with a reduced
+//! reproducer, which is also saved with `--corpus DIR`.
 
-use anyhow::{bail, Result};
+use anyhow::{bail, format_err, Result};
+use cargo_util_schemas::manifest::TomlManifest;
+use crates_scanner::parser_diff::{from_fn, Harness, NormalizedValue};
+use crates_scanner::reduce::Corpus;
+use serde::Deserialize;
 use std::path::Path;
+use std::sync::Arc;
+
+const USAGE: &str = "usage: toml-scanner CRATES_PATH [--corpus DIR]";
 
 fn main() {
-    let crates_path = std::env::args()
-        .skip(1)
-        .next()
-        .expect("first argument must be a path to the crates directory");
+    let (crates_path, corpus) = match parse_args() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("error: {e}\n{USAGE}");
+            std::process::exit(1);
+        }
+    };
+
+    // General parse check, of the new parser on its own against what the
+    // old one deserializes into a new `toml::Value`.
+    let mut values = Harness::new()
+        .parser(from_fn("toml", |s| {
+            let table = toml::de::DeTable::parse(s)?;
+            Ok(NormalizedValue::deserialize(toml::de::Deserializer::from(
+                table,
+            ))?)
+        }))
+        .parser(from_fn("toml 0.8", |s| {
+            let value = toml_v08::from_str::<toml::Value>(s)?;
+            Ok(NormalizedValue::deserialize(value)?)
+        }));
+    // Deserialization check
+    let mut manifests = Harness::new()
+        .parser(from_fn("toml manifest", |s| normalize(toml::from_str(s)?)))
+        .parser(from_fn("toml 0.8 manifest", |s| {
+            normalize(toml_v08::from_str(s)?)
+        }));
+    if let Some(dir) = corpus {
+        let corpus = Arc::new(Corpus::create(&dir, "toml").unwrap());
+        values = values.corpus(corpus.clone());
+        manifests = manifests.corpus(corpus);
+    }
 
     crates_scanner::scan_compressed(
         Path::new(&crates_path),
         crates_scanner::Versions::All,
-        |path| path.file_name().map_or(false, |n| n == "Cargo.toml"),
-        check_parse,
+        |path| path.file_name().is_some_and(|n| n == "Cargo.toml"),
+        |crate_path, entry_path, contents| {
+            let krate = crates_scanner::crate_name(crate_path);
+            values.report(&krate, entry_path, contents)?;
+            manifests.report(&krate, entry_path, contents)
+        },
     );
+    println!("parsing:");
+    values.print();
+    println!("deserialization:");
+    manifests.print();
 }
 
-fn check_parse(crate_path: &Path, _entry_path: &Path, contents: &str) -> Result<()> {
-    // General parse check
-    let new_value = toml::de::DeTable::parse(&contents);
-    let old_value = toml_v08::from_str::<toml::Value>(&contents);
-    match (old_value.is_ok(), new_value.is_ok()) {
-        (false, true) => {
-            eprintln!(
-                "{crate_path:?} parsing succeeded when it previously failed: {old_value:?}\ncontents: {contents}");
-        }
-        (true, false) => {
-            eprintln!(
-                "{crate_path:?} parsing failed when it previously succeeded: {new_value:?}\ncontents: {contents}");
-        }
-        _ => {}
-    }
-
-    // Deserialization check
-    let new_value = toml::from_str::<cargo_util_schemas::manifest::TomlManifest>(&contents);
-    let old_value = toml_v08::from_str::<cargo_util_schemas::manifest::TomlManifest>(&contents);
-    match (old_value.is_ok(), new_value.is_ok()) {
-        (false, true) => {
-            eprintln!(
-                "{crate_path:?} deserialization succeeded when it previously failed: {old_value:?}\ncontents: {contents}");
-        }
-        (true, false) => {
-            eprintln!(
-                "{crate_path:?} deserialization failed when it previously succeeded: {new_value:?}\ncontents: {contents}");
+fn parse_args() -> Result<(String, Option<String>)> {
+    let mut corpus = None;
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--corpus" => {
+                corpus = Some(
+                    args.next()
+                        .ok_or_else(|| format_err!("`--corpus` requires a value"))?,
+                );
+            }
+            s if s.starts_with("--") => bail!("unexpected argument `{s}`"),
+            _ => positional.push(arg),
         }
-        _ => {}
-
     }
+    let [crates_path] = <[String; 1]>::try_from(positional)
+        .map_err(|_| format_err!("expected CRATES_PATH"))?;
+    Ok((crates_path, corpus))
+}
 
-    Ok(())
+/// Turns a deserialized manifest back into a value, so the manifests from
+/// both versions can be compared.
+fn normalize(manifest: TomlManifest) -> Result<NormalizedValue> {
+    Ok(NormalizedValue::deserialize(serde_json::to_value(manifest)?)?)
 }
 
+// Other checks, which can be run instead of the harnesses.
+
+#[allow(dead_code)]
 fn check_tab(crate_path: &Path, _entry_path: &Path, contents: &str) -> Result<()> {
     let v = match toml::from_str::<toml::Value>(&contents) {
         Ok(v) => v,
@@ -63,12 +104,11 @@ fn check_tab(crate_path: &Path, _entry_path: &Path, contents: &str) -> Result<()
     Ok(())
 }
 
+#[allow(dead_code)]
 fn check_tab_v(path: &Path, contents: &str, v: &toml::Value) {
     match v {
-        toml::Value::String(s) => {
-            if s.contains('\t') && contents.contains('\t') {
-                eprintln!("{path:?}: {s:?}", );
-            }
+        toml::Value::String(s) if s.contains('\t') && contents.contains('\t') => {
+            eprintln!("{path:?}: {s:?}");
         }
         toml::Value::Array(a) => {
             for v in a {
@@ -85,6 +125,7 @@ fn check_tab_v(path: &Path, contents: &str, v: &toml::Value) {
 
 }
 
+#[allow(dead_code)]
 fn check_manifest(crate_path: &Path, _entry_path: &Path, contents: &str) -> Result<()> {
     let v = match toml::from_str::<toml::Value>(&contents) {
         Ok(v) => v,
@@ -140,6 +181,7 @@ fn check_manifest(crate_path: &Path, _entry_path: &Path, contents: &str) -> Resu
     Ok(())
 }
 
+#[allow(dead_code)]
 fn check_deps(path: &Path, contents: &str, deps: Option<&toml::Value>) {
     let deps = match deps {
         Some(d) => match d.as_table() {
@@ -162,11 +204,9 @@ fn check_deps(path: &Path, contents: &str, deps: Option<&toml::Value>) {
                 return;
             }
         };
-        if let Some(o) = t.get("optional") {
-            if name.contains("derive") {
-                println!("found {name} in {path:?}");
-                println!("{contents}");
-            }
+        if t.contains_key("optional") && name.contains("derive") {
+            println!("found {name} in {path:?}");
+            println!("{contents}");
         }
         // if t.keys().next().is_none() {
         //     println!("found match: {path:?}");
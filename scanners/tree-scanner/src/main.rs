@@ -1,41 +1,106 @@
 //! Runs `cargo tree` on every crate.
+//!
+//! Failures are classified with `crates_scanner::cargo::Failure` and written
+//! to `tree_results.jsonl`, one line per crate, with counts per category
+//! printed at the end. With `--baseline CARGO`, every crate is also run
+//! with that cargo, and crates whose category changed are counted, which is
+//! what to look at when checking a cargo change.
 
-use anyhow::bail;
-use std::io::Write;
+use anyhow::{bail, format_err, Result};
+use crates_scanner::cargo::{CargoRunner, Failure};
+use crates_scanner::differential::{Normalizer, ReplacePaths};
+use crates_scanner::results::{Sink, Tally};
+use serde_json::json;
 use std::path::Path;
 
+const USAGE: &str =
+    "usage: tree-scanner SRC_PATH CARGO_PATH [--baseline CARGO] [cargo runner options]";
+
 fn main() {
-    let mut args = std::env::args().skip(1);
-    let src_path = args
-        .next()
-        .expect("first argument must be a path to the extracted source directory");
-    let cargo_path = args
-        .next()
-        .expect("second argument must be a path to your locally built cargo");
+    let (runner, baseline, src_path) = match parse_args() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("error: {e}\n{USAGE}\n{}", crates_scanner::cargo::USAGE);
+            std::process::exit(1);
+        }
+    };
     crates_scanner::overdrive(2);
 
-    crates_scanner::scan_uncompressed(Path::new(&src_path), |path| {
-        let output = std::process::Command::new(&cargo_path)
-            .args(&["tree", "-Zno-index-update"])
-            .current_dir(path)
-            .output()
-            .unwrap();
-        if !output.status.success() {
-            let stderr = std::str::from_utf8(&output.stderr).unwrap();
-            let stdout = std::str::from_utf8(&output.stdout).unwrap();
-            let mut f = std::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open("tree_results.txt")
-                .unwrap();
-            writeln!(
-                f,
-                "Failed: {path:?}\n---stderr\n{stderr}\n--stdout\n{stdout}\n"
-            )
-            .unwrap();
-            bail!("{path:?} FAILED!!!");
-        }
+    let results = Sink::create("tree_results.jsonl").unwrap();
+    let failures = Tally::new(5);
+    let baseline_failures = Tally::new(5);
+    let changes = Tally::new(5);
 
-        Ok(())
+    let scanned = crates_scanner::scan_uncompressed(Path::new(&src_path), |path| {
+        let krate = path.file_name().unwrap().to_str().unwrap();
+        let (failure, message) = tree(&runner, path)?;
+        if let Some(failure) = failure {
+            failures.add(failure.to_string(), krate);
+        }
+        let mut record = json!({
+            "crate": krate,
+            "path": path,
+            "category": category(failure),
+            "error": message,
+        });
+        if let Some(baseline) = &baseline {
+            let (base_failure, base_message) = tree(baseline, path)?;
+            if let Some(failure) = base_failure {
+                baseline_failures.add(failure.to_string(), krate);
+            }
+            if base_failure != failure {
+                let change = format!("{} -> {}", category(base_failure), category(failure));
+                changes.add(change, krate);
+            }
+            record["baseline_category"] = json!(category(base_failure));
+            record["baseline_error"] = json!(base_message);
+        }
+        results.write(&record)
     });
+    if let Err(e) = scanned {
+        eprintln!("error: {e:#}");
+        std::process::exit(1);
+    }
+    results.flush().unwrap();
+
+    failures.print("failures");
+    if baseline.is_some() {
+        baseline_failures.print("baseline failures");
+        changes.print("changed categories (baseline -> new)");
+    }
+}
+
+fn parse_args() -> Result<(CargoRunner, Option<CargoRunner>, String)> {
+    let (runner, args) = CargoRunner::from_args("cargo", std::env::args().skip(1))?;
+    let mut baseline = None;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--baseline" => {
+                let cargo = args
+                    .next()
+                    .ok_or_else(|| format_err!("`--baseline` requires a value"))?;
+                baseline = Some(runner.with_cargo(cargo));
+            }
+            s if s.starts_with("--") => bail!("unexpected argument `{s}`"),
+            _ => positional.push(arg),
+        }
+    }
+    let [src_path, cargo_path] = <[String; 2]>::try_from(positional)
+        .map_err(|_| format_err!("expected SRC_PATH and CARGO_PATH"))?;
+    Ok((runner.with_cargo(cargo_path), baseline, src_path))
+}
+
+/// Runs `cargo tree`, returning how it failed and the error message with
+/// the scratch path replaced.
+fn tree(runner: &CargoRunner, path: &Path) -> Result<(Option<Failure>, String)> {
+    let ws = runner.prepare(path)?;
+    let output = runner.run(&ws, &["tree"])?;
+    let message = ReplacePaths.normalize(&ws, Failure::message(&output).to_string());
+    Ok((Failure::classify(&output), message))
+}
+
+fn category(failure: Option<Failure>) -> String {
+    failure.map_or_else(|| "ok".to_string(), |f| f.to_string())
 }
@@ -0,0 +1,475 @@
+//! Scanning Rust source with syn.
+//!
+//! An [`AstScanner`] has hooks for the syn nodes it cares about, and
+//! [`run`] calls them for every `.rs` file, passing a [`Context`] with the
+//! crate and file being scanned. Findings are reported through the context
+//! and printed with a source excerpt.
+//!
+//! Files are parsed knowing the edition cargo compiles them with, which
+//! comes from the crate's manifest. Every file gets a [`ParseOutcome`], so
+//! a file syn can't parse is told apart from one that isn't meant to be a
+//! module at all, and the summary counts crates per edition along with how
+//! many had every compiled file parsed. Those are the denominators for
+//! statements like "X% of 2021 edition crates use Y".
+//!
+//! A complete scanner looks like:
+//!
+//! ```ignore
+//! struct TodoMacros;
+//!
+//! impl AstScanner for TodoMacros {
+//!     fn mac(&self, cx: &mut Context<'_>, mac: &syn::Macro) {
+//!         if mac.path.is_ident("todo") {
+//!             cx.report(mac.path.span(), "todo!() invocation");
+//!         }
+//!     }
+//! }
+//!
+//! fn main() {
+//!     crates_scanner::ast::run(&TodoMacros);
+//! }
+//! ```
+
+use crate::findings::{Finding, Span};
+use crate::manifest::Manifest;
+use crate::modules::{self, CrateFiles, FileKind};
+use crate::results::{Sink, Tally};
+use crate::{CrateSources, Selection, Versions};
+use anyhow::{bail, format_err, Result};
+use proc_macro2::{Group, Ident, TokenStream, TokenTree};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use syn::parse::Parser;
+use syn::visit::{self, Visit};
+
+pub const USAGE: &str = "\
+usage: SCANNER CRATES_PATH [selection options] [--kinds KINDS] [--parse-results FILE]
+       SCANNER --extracted SRC_PATH [--kinds KINDS] [--parse-results FILE]
+
+--kinds KINDS         only scan files compiled as one of KINDS, a comma-separated
+                      list of lib, bin, build, example, test, bench, unreachable
+--parse-results FILE  write the parse outcome of every file as JSON lines";
+
+/// Hooks called for syn nodes. Every hook does nothing by default, and
+/// nodes inside a node are visited whether or not its hook is implemented.
+#[allow(unused_variables)]
+pub trait AstScanner: Sync {
+    /// Called once per file, before any other hook.
+    fn file(&self, cx: &mut Context<'_>, file: &syn::File) {}
+    fn item(&self, cx: &mut Context<'_>, item: &syn::Item) {}
+    fn item_fn(&self, cx: &mut Context<'_>, item: &syn::ItemFn) {}
+    fn impl_item_fn(&self, cx: &mut Context<'_>, item: &syn::ImplItemFn) {}
+    fn item_use(&self, cx: &mut Context<'_>, item: &syn::ItemUse) {}
+    fn attribute(&self, cx: &mut Context<'_>, attr: &syn::Attribute) {}
+    fn expr(&self, cx: &mut Context<'_>, expr: &syn::Expr) {}
+    fn expr_call(&self, cx: &mut Context<'_>, expr: &syn::ExprCall) {}
+    fn expr_method_call(&self, cx: &mut Context<'_>, expr: &syn::ExprMethodCall) {}
+    /// A macro invocation, in any position.
+    fn mac(&self, cx: &mut Context<'_>, mac: &syn::Macro) {}
+    fn lit(&self, cx: &mut Context<'_>, lit: &syn::Lit) {}
+    fn path(&self, cx: &mut Context<'_>, path: &syn::Path) {}
+    fn ty(&self, cx: &mut Context<'_>, ty: &syn::Type) {}
+}
+
+/// The file being scanned, and the findings reported in it.
+pub struct Context<'a> {
+    /// The crate, like `serde@1.0.0`.
+    pub krate: &'a str,
+    /// The path of the file, starting with the crate's directory.
+    pub path: &'a Path,
+    /// The edition the file is compiled with, like `2021`, or `unknown` if
+    /// the manifest couldn't be read.
+    pub edition: &'a str,
+    /// What the file is compiled as, or `None` if the manifest couldn't be
+    /// read.
+    pub kind: Option<FileKind>,
+    /// The crate's manifest, `None` if it couldn't be read.
+    pub manifest: Option<&'a Manifest>,
+    pub contents: &'a str,
+    findings: Vec<Finding>,
+}
+
+impl Context<'_> {
+    /// Reports a finding at `span`.
+    pub fn report(&mut self, span: impl Into<Span>, message: impl Into<String>) {
+        self.findings
+            .push(Finding::new(self.path, span.into(), message));
+    }
+
+    /// The source text of `span`.
+    pub fn source(&self, span: impl Into<Span>) -> &str {
+        &self.contents[span.into().byte_range(self.contents)]
+    }
+}
+
+/// How a file parsed, or why it didn't.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ParseOutcome {
+    Parsed,
+    /// Parsed after treating `async`, `await` and `try` as identifiers,
+    /// which they are in the 2015 edition.
+    Parsed2015Keywords,
+    /// Not a module, but an expression or statements to be `include!`d.
+    IncludeFragment,
+    /// Didn't parse, but is a module of a target. Published crates were
+    /// accepted by rustc, so this is most likely syntax newer than syn
+    /// supports (or a module that is `cfg`d out on every platform).
+    NewerSyntax,
+    /// Didn't parse and isn't a module of any target, like a compile-fail
+    /// test.
+    Invalid,
+}
+
+impl ParseOutcome {
+    /// Whether the file was parsed as a module and scanned.
+    pub fn is_parsed(self) -> bool {
+        matches!(
+            self,
+            ParseOutcome::Parsed | ParseOutcome::Parsed2015Keywords
+        )
+    }
+}
+
+impl fmt::Display for ParseOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ParseOutcome::Parsed => "parsed",
+            ParseOutcome::Parsed2015Keywords => "parsed-2015-keywords",
+            ParseOutcome::IncludeFragment => "include-fragment",
+            ParseOutcome::NewerSyntax => "newer-syntax",
+            ParseOutcome::Invalid => "invalid",
+        })
+    }
+}
+
+/// A file that didn't parse as a module.
+#[derive(Debug)]
+pub struct ParseFailure {
+    /// [`ParseOutcome::IncludeFragment`], [`ParseOutcome::NewerSyntax`] or
+    /// [`ParseOutcome::Invalid`].
+    pub outcome: ParseOutcome,
+    /// The error from parsing the file as a module.
+    pub error: syn::Error,
+}
+
+/// Runs `scanner` on the crates given on the command line, and prints the
+/// findings and a summary. See [`USAGE`].
+pub fn run(scanner: &impl AstScanner) {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    if let Err(e) = run_with_args(scanner, args) {
+        eprintln!("error: {e:#}\n{USAGE}\n{}", crate::selection::USAGE);
+        std::process::exit(1);
+    }
+}
+
+fn run_with_args(scanner: &impl AstScanner, args: Vec<String>) -> Result<()> {
+    let mut sink = None;
+    let mut kinds = None;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--parse-results" {
+            let path = args
+                .next()
+                .ok_or_else(|| format_err!("`--parse-results` requires a value"))?;
+            sink = Some(Sink::create(path)?);
+        } else if arg == "--kinds" {
+            let list = args
+                .next()
+                .ok_or_else(|| format_err!("`--kinds` requires a value"))?;
+            kinds = Some(modules::parse_kinds(&list)?);
+        } else {
+            rest.push(arg);
+        }
+    }
+    let args = rest;
+
+    // The package edition of every crate scanned, and the crates where a
+    // compiled file didn't parse.
+    let editions = Mutex::new(HashMap::new());
+    let failed = Mutex::new(HashSet::new());
+    let findings = Tally::new(5);
+    let outcomes = Tally::new(5);
+    let file_kinds = Tally::new(5);
+    let parse_errors = Tally::new(5);
+    let scan_one = |files: &CrateFiles, path: &Path, contents: &str| -> Result<()> {
+        let krate = &files.krate;
+        let kind = files.kind(path);
+        if let Some(kinds) = &kinds {
+            if !kind.is_some_and(|kind| kinds.contains(&kind)) {
+                return Ok(());
+            }
+        }
+        let edition = files.edition(path).unwrap_or("unknown");
+        editions
+            .lock()
+            .unwrap()
+            .entry(krate.clone())
+            .or_insert_with(|| {
+                let package_edition = files.manifest.as_ref().map_or("unknown", |m| &m.edition);
+                package_edition.to_string()
+            });
+        let outcome = match scan_file(scanner, files, path, contents) {
+            Ok((outcome, file_findings)) => {
+                for finding in file_findings {
+                    eprintln!("{}", finding.render(contents));
+                    findings.add(finding.message, krate);
+                }
+                if let Some(sink) = &sink {
+                    sink.write(&json!({
+                        "crate": krate,
+                        "path": path,
+                        "edition": edition,
+                        "kind": kind.map(|k| k.to_string()),
+                        "outcome": outcome.to_string(),
+                    }))?;
+                }
+                outcome
+            }
+            Err(failure) => {
+                let outcome = failure.outcome;
+                if outcome != ParseOutcome::IncludeFragment {
+                    parse_errors.add(format!("{outcome}: {}", failure.error), krate);
+                }
+                if outcome == ParseOutcome::NewerSyntax {
+                    failed.lock().unwrap().insert(krate.clone());
+                }
+                if let Some(sink) = &sink {
+                    let start = failure.error.span().start();
+                    sink.write(&json!({
+                        "crate": krate,
+                        "path": path,
+                        "edition": edition,
+                        "kind": kind.map(|k| k.to_string()),
+                        "outcome": outcome.to_string(),
+                        "error": failure.error.to_string(),
+                        "line": start.line,
+                        "column": start.column + 1,
+                    }))?;
+                }
+                outcome
+            }
+        };
+        outcomes.add(format!("edition {edition}: {outcome}"), krate);
+        file_kinds.add(kind.map_or("unknown".to_string(), |k| k.to_string()), krate);
+        Ok(())
+    };
+    let scan_crate = |krate: &CrateSources| {
+        let files = CrateFiles::new(krate);
+        for (path, contents) in &krate.sources {
+            if path.extension().is_some_and(|ext| ext == "rs") {
+                scan_one(&files, path, contents)?;
+            }
+        }
+        Ok(())
+    };
+
+    let filter = modules::is_package_file;
+    if args.first().is_some_and(|arg| arg == "--extracted") {
+        let [_, src_path] = <[String; 2]>::try_from(args)
+            .map_err(|_| format_err!("expected SRC_PATH after --extracted"))?;
+        crate::scan_uncompressed_crates(Path::new(&src_path), filter, scan_crate)?;
+    } else {
+        let (selection, args) = Selection::from_args(Versions::All, args)?;
+        if let Some(arg) = args.iter().find(|arg| arg.starts_with("--")) {
+            bail!("unexpected argument `{arg}`");
+        }
+        let [crates_path] =
+            <[String; 1]>::try_from(args).map_err(|_| format_err!("expected CRATES_PATH"))?;
+        crate::scan_compressed_crates(Path::new(&crates_path), selection, filter, scan_crate);
+    }
+    if let Some(sink) = &sink {
+        sink.flush()?;
+    }
+
+    let crate_editions = Tally::new(5);
+    let failed = failed.into_inner().unwrap();
+    for (krate, edition) in editions.into_inner().unwrap() {
+        crate_editions.add(format!("edition {edition}"), &krate);
+        if !failed.contains(&krate) {
+            crate_editions.add(
+                format!("edition {edition}, every compiled file parsed"),
+                &krate,
+            );
+        }
+    }
+    findings.print("findings");
+    parse_errors.print("parse errors");
+    outcomes.print("files by edition and parse outcome");
+    file_kinds.print("files by kind");
+    crate_editions.print("crates by edition");
+    Ok(())
+}
+
+/// Parses one file of `files` and runs `scanner` on it, returning how it
+/// parsed and its findings.
+///
+/// Files of crates whose manifest couldn't be read are assumed to be
+/// compiled if they are under `src/` or are `build.rs`.
+pub fn scan_file(
+    scanner: &impl AstScanner,
+    files: &CrateFiles,
+    path: &Path,
+    contents: &str,
+) -> Result<(ParseOutcome, Vec<Finding>), ParseFailure> {
+    let edition = files.edition(path).unwrap_or("unknown");
+    let kind = files.kind(path);
+    let compiled = match kind {
+        Some(kind) => kind != FileKind::Unreachable,
+        None => {
+            let package_path: PathBuf = path.components().skip(1).collect();
+            package_path.starts_with("src") || package_path == Path::new("build.rs")
+        }
+    };
+    let (file, outcome) = parse(contents, edition, compiled)?;
+    let mut cx = Context {
+        krate: &files.krate,
+        path,
+        edition,
+        kind,
+        manifest: files.manifest.as_ref(),
+        contents,
+        findings: Vec::new(),
+    };
+    scanner.file(&mut cx, &file);
+    Hooks {
+        scanner,
+        cx: &mut cx,
+    }
+    .visit_file(&file);
+    Ok((outcome, cx.findings))
+}
+
+/// Parses a file compiled with `edition`.
+///
+/// `compiled` is whether a target reaches the file as a module, which
+/// decides between [`ParseOutcome::NewerSyntax`] and
+/// [`ParseOutcome::Invalid`] for a file that doesn't parse. Spans in the
+/// returned file always point into `contents`, even for
+/// [`ParseOutcome::Parsed2015Keywords`], where the keywords used as
+/// identifiers become raw identifiers like `r#async`.
+pub fn parse(
+    contents: &str,
+    edition: &str,
+    compiled: bool,
+) -> Result<(syn::File, ParseOutcome), ParseFailure> {
+    let error = match syn::parse_file(contents) {
+        Ok(file) => return Ok((file, ParseOutcome::Parsed)),
+        Err(e) => e,
+    };
+    if edition == "2015" {
+        if let Ok(tokens) = contents.parse::<TokenStream>() {
+            let (tokens, changed) = raw_2015_keywords(tokens);
+            if changed {
+                if let Ok(file) = syn::parse2(tokens) {
+                    return Ok((file, ParseOutcome::Parsed2015Keywords));
+                }
+            }
+        }
+    }
+    let outcome = if syn::parse_str::<syn::Expr>(contents).is_ok()
+        || syn::Block::parse_within.parse_str(contents).is_ok()
+    {
+        ParseOutcome::IncludeFragment
+    } else if compiled {
+        ParseOutcome::NewerSyntax
+    } else {
+        ParseOutcome::Invalid
+    };
+    Err(ParseFailure { outcome, error })
+}
+
+/// Turns identifiers that are keywords since the 2018 edition into raw
+/// identifiers, keeping their spans. Returns whether there were any.
+fn raw_2015_keywords(tokens: TokenStream) -> (TokenStream, bool) {
+    let mut changed = false;
+    let tokens = tokens
+        .into_iter()
+        .map(|tt| match tt {
+            TokenTree::Ident(ident) if ["async", "await", "try"].contains(&&*ident.to_string()) => {
+                changed = true;
+                TokenTree::Ident(Ident::new_raw(&ident.to_string(), ident.span()))
+            }
+            TokenTree::Group(group) => {
+                let (stream, group_changed) = raw_2015_keywords(group.stream());
+                changed |= group_changed;
+                let mut new = Group::new(group.delimiter(), stream);
+                new.set_span(group.span());
+                TokenTree::Group(new)
+            }
+            tt => tt,
+        })
+        .collect();
+    (tokens, changed)
+}
+
+/// Calls the hooks while visiting every node.
+struct Hooks<'a, 'b, S> {
+    scanner: &'a S,
+    cx: &'a mut Context<'b>,
+}
+
+impl<'ast, S: AstScanner> Visit<'ast> for Hooks<'_, '_, S> {
+    fn visit_item(&mut self, i: &'ast syn::Item) {
+        self.scanner.item(self.cx, i);
+        visit::visit_item(self, i);
+    }
+
+    fn visit_item_fn(&mut self, i: &'ast syn::ItemFn) {
+        self.scanner.item_fn(self.cx, i);
+        visit::visit_item_fn(self, i);
+    }
+
+    fn visit_impl_item_fn(&mut self, i: &'ast syn::ImplItemFn) {
+        self.scanner.impl_item_fn(self.cx, i);
+        visit::visit_impl_item_fn(self, i);
+    }
+
+    fn visit_item_use(&mut self, i: &'ast syn::ItemUse) {
+        self.scanner.item_use(self.cx, i);
+        visit::visit_item_use(self, i);
+    }
+
+    fn visit_attribute(&mut self, i: &'ast syn::Attribute) {
+        self.scanner.attribute(self.cx, i);
+        visit::visit_attribute(self, i);
+    }
+
+    fn visit_expr(&mut self, i: &'ast syn::Expr) {
+        self.scanner.expr(self.cx, i);
+        visit::visit_expr(self, i);
+    }
+
+    fn visit_expr_call(&mut self, i: &'ast syn::ExprCall) {
+        self.scanner.expr_call(self.cx, i);
+        visit::visit_expr_call(self, i);
+    }
+
+    fn visit_expr_method_call(&mut self, i: &'ast syn::ExprMethodCall) {
+        self.scanner.expr_method_call(self.cx, i);
+        visit::visit_expr_method_call(self, i);
+    }
+
+    fn visit_macro(&mut self, i: &'ast syn::Macro) {
+        self.scanner.mac(self.cx, i);
+        visit::visit_macro(self, i);
+    }
+
+    fn visit_lit(&mut self, i: &'ast syn::Lit) {
+        self.scanner.lit(self.cx, i);
+        visit::visit_lit(self, i);
+    }
+
+    fn visit_path(&mut self, i: &'ast syn::Path) {
+        self.scanner.path(self.cx, i);
+        visit::visit_path(self, i);
+    }
+
+    fn visit_type(&mut self, i: &'ast syn::Type) {
+        self.scanner.ty(self.cx, i);
+        visit::visit_type(self, i);
+    }
+}
@@ -0,0 +1,396 @@
+//! Running cargo against extracted crates without touching them.
+//!
+//! [`CargoRunner`] copies each crate into a scratch directory before running
+//! cargo in it, so `Cargo.lock`, `target/` and anything else cargo writes
+//! never end up in the extracted tree, and several scanners can run against
+//! the same tree at once. Cargo gets its own target directory, runs in
+//! offline mode, and is run with [`Limits`] so it is killed if it runs too
+//! long or uses too much memory.
+//!
+//! Offline mode only works if every dependency is available locally. By
+//! default cargo uses the user's `CARGO_HOME`, so dependencies come from its
+//! registry cache. With `--registry`, crates.io is replaced by a local
+//! registry built by the local-registry tool in a `CARGO_HOME` of its own,
+//! so resolution depends only on that registry and not on whatever is in
+//! the cache of the machine running the scan.
+
+use crate::extract::MARKER_FILE;
+use crate::process::{self, Limits, Output};
+use anyhow::{bail, format_err, Context, Result};
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// The first line of a `config.toml` written by [`CargoRunner::registry`].
+const CONFIG_HEADER: &str = "# Generated by crates-scanner, changes will be overwritten.";
+
+pub const USAGE: &str = "\
+cargo runner options:
+    --scratch DIR       directory for crate copies (default: a temp directory)
+    --cargo-home DIR    CARGO_HOME to use. Cargo runs offline, so its registry cache must have
+                        every dependency (default: `$CARGO_HOME` or `~/.cargo`, or with
+                        --registry, `cargo-home` in the scratch directory)
+    --timeout SECS      kill cargo after this many seconds (default: 600)
+    --memory-limit MB   limit the memory of each process cargo runs
+    --registry PATH     use a local registry built by local-registry instead of crates.io";
+
+/// Runs a cargo binary in isolated copies of crates.
+#[derive(Clone, Debug)]
+pub struct CargoRunner {
+    cargo: PathBuf,
+    scratch: PathBuf,
+    cargo_home: PathBuf,
+    limits: Limits,
+    envs: Vec<(OsString, OsString)>,
+}
+
+/// A scratch copy of a crate, removed when dropped.
+pub struct Workspace {
+    dir: TempDir,
+    crate_dir: PathBuf,
+}
+
+impl Workspace {
+    /// The copy of the crate.
+    pub fn path(&self) -> &Path {
+        &self.crate_dir
+    }
+
+    /// The target directory cargo uses for this workspace.
+    pub fn target_dir(&self) -> PathBuf {
+        self.dir.path().join("target")
+    }
+}
+
+impl CargoRunner {
+    /// Creates a runner for the cargo binary `cargo`, using `scratch` for
+    /// crate copies and a `CARGO_HOME` in it, which has no dependencies
+    /// until [`Self::registry`] sets one up.
+    pub fn new(cargo: impl Into<PathBuf>, scratch: impl Into<PathBuf>) -> Result<CargoRunner> {
+        let scratch = scratch.into();
+        fs::create_dir_all(&scratch).with_context(|| format!("failed to create {scratch:?}"))?;
+        let scratch = scratch.canonicalize()?;
+        Ok(CargoRunner {
+            cargo: cargo.into(),
+            cargo_home: scratch.join("cargo-home"),
+            scratch,
+            limits: Limits {
+                timeout: Some(Duration::from_secs(600)),
+                memory: None,
+            },
+            envs: Vec::new(),
+        })
+    }
+
+    /// Parses the options in [`USAGE`] from command-line arguments.
+    ///
+    /// Arguments that are not runner options are returned in order so the
+    /// caller can handle them.
+    pub fn from_args(
+        cargo: impl Into<PathBuf>,
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(CargoRunner, Vec<String>)> {
+        let mut scratch = std::env::temp_dir().join("crates-scanner-scratch");
+        let mut cargo_home = None;
+        let mut timeout = Some(Duration::from_secs(600));
+        let mut memory = None;
+        let mut registry = None;
+        let mut rest = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format_err!("`{arg}` requires a value"))
+            };
+            match arg.as_str() {
+                "--scratch" => scratch = PathBuf::from(value()?),
+                "--cargo-home" => cargo_home = Some(PathBuf::from(value()?)),
+                "--timeout" => {
+                    let secs = value()?;
+                    let secs = secs
+                        .parse()
+                        .with_context(|| format!("invalid --timeout value `{secs}`"))?;
+                    timeout = Some(Duration::from_secs(secs));
+                }
+                "--memory-limit" => {
+                    let mb = value()?;
+                    let mb: u64 = mb
+                        .parse()
+                        .with_context(|| format!("invalid --memory-limit value `{mb}`"))?;
+                    memory = Some(mb * 1024 * 1024);
+                }
+                "--registry" => registry = Some(PathBuf::from(value()?)),
+                _ => rest.push(arg.clone()),
+            }
+        }
+        let mut runner = CargoRunner::new(cargo, scratch)?;
+        match (cargo_home, &registry) {
+            (Some(cargo_home), _) => runner = runner.cargo_home(cargo_home),
+            // The `CARGO_HOME` in the scratch directory is empty, so without
+            // a registry nothing with dependencies would resolve offline.
+            (None, None) => runner = runner.cargo_home(user_cargo_home()?),
+            (None, Some(_)) => {}
+        }
+        runner.limits = Limits { timeout, memory };
+        match registry {
+            Some(registry) => runner = runner.registry(&registry)?,
+            // Don't keep using the registry from an earlier run.
+            None => remove_generated_config(&runner.cargo_home)?,
+        }
+        Ok((runner, rest))
+    }
+
+    /// Returns a runner with the same settings for a different cargo binary.
+    pub fn with_cargo(&self, cargo: impl Into<PathBuf>) -> CargoRunner {
+        CargoRunner {
+            cargo: cargo.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Sets the `CARGO_HOME` used for every command.
+    ///
+    /// This is where cargo looks for its registry cache and `config.toml`,
+    /// so pointing it at a prepared directory controls what dependencies
+    /// are available offline.
+    pub fn cargo_home(mut self, path: impl Into<PathBuf>) -> CargoRunner {
+        self.cargo_home = path.into();
+        self
+    }
+
+    /// Sets how long a command may run before it is killed, or `None` to
+    /// wait forever.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> CargoRunner {
+        self.limits.timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum virtual memory in bytes of every process cargo
+    /// runs, or `None` for no limit.
+    pub fn memory_limit(mut self, memory: Option<u64>) -> CargoRunner {
+        self.limits.memory = memory;
+        self
+    }
+
+    /// Replaces crates.io with the local registry at `path`.
+    ///
+    /// This writes a `config.toml` into the `CARGO_HOME`, so it must be
+    /// called after [`Self::cargo_home`]. A `config.toml` that wasn't
+    /// written by this is never overwritten.
+    pub fn registry(self, path: &Path) -> Result<CargoRunner> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("failed to find registry {path:?}"))?;
+        if !path.join("index").exists() {
+            bail!("{path:?} is not a local registry");
+        }
+        let config_path = self.cargo_home.join("config.toml");
+        if let Ok(existing) = fs::read_to_string(&config_path) {
+            if !existing.starts_with(CONFIG_HEADER) {
+                bail!(
+                    "refusing to overwrite {config_path:?}, it was not written by crates-scanner"
+                );
+            }
+        }
+        let path = toml::Value::String(path.to_str().unwrap().to_string());
+        let config = format!(
+            "{CONFIG_HEADER}\n\
+            [source.crates-io]\n\
+            replace-with = \"local-registry\"\n\
+            \n\
+            [source.local-registry]\n\
+            local-registry = {path}\n"
+        );
+        fs::create_dir_all(&self.cargo_home)?;
+        fs::write(&config_path, config)
+            .with_context(|| format!("failed to write {config_path:?}"))?;
+        Ok(self)
+    }
+
+    /// Sets an extra environment variable for every command.
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> CargoRunner {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// Copies the crate at `crate_dir` into a new scratch workspace.
+    pub fn prepare(&self, crate_dir: &Path) -> Result<Workspace> {
+        let dir_name = crate_dir.file_name().unwrap();
+        let dir = tempfile::Builder::new()
+            .prefix(&format!("{}-", dir_name.to_string_lossy()))
+            .tempdir_in(&self.scratch)
+            .with_context(|| format!("failed to create workspace in {:?}", self.scratch))?;
+        let copy = dir.path().join(dir_name);
+        copy_dir(crate_dir, &copy)
+            .with_context(|| format!("failed to copy {crate_dir:?} to {copy:?}"))?;
+        Ok(Workspace {
+            dir,
+            crate_dir: copy,
+        })
+    }
+
+    /// Runs cargo with `args` in a workspace created by [`Self::prepare`].
+    pub fn run(&self, ws: &Workspace, args: &[&str]) -> Result<Output> {
+        let mut cmd = Command::new(&self.cargo);
+        // Don't let settings from whatever started us leak in.
+        for (key, _) in std::env::vars_os() {
+            if key.to_string_lossy().starts_with("CARGO_") {
+                cmd.env_remove(key);
+            }
+        }
+        cmd.args(args)
+            .current_dir(ws.path())
+            .env("CARGO_HOME", &self.cargo_home)
+            .env("CARGO_TARGET_DIR", ws.target_dir())
+            .env("CARGO_NET_OFFLINE", "true")
+            .envs(self.envs.iter().map(|(k, v)| (k, v)));
+        process::run(&mut cmd, &self.limits)
+    }
+}
+
+/// The `CARGO_HOME` cargo uses when run by the user.
+fn user_cargo_home() -> Result<PathBuf> {
+    if let Some(home) = std::env::var_os("CARGO_HOME").filter(|home| !home.is_empty()) {
+        return Ok(PathBuf::from(home));
+    }
+    let home = std::env::home_dir()
+        .ok_or_else(|| format_err!("failed to find the home directory, pass --cargo-home"))?;
+    Ok(home.join(".cargo"))
+}
+
+/// Removes a `config.toml` written by [`CargoRunner::registry`].
+fn remove_generated_config(cargo_home: &Path) -> Result<()> {
+    let config_path = cargo_home.join("config.toml");
+    if let Ok(existing) = fs::read_to_string(&config_path) {
+        if existing.starts_with(CONFIG_HEADER) {
+            fs::remove_file(&config_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Why a cargo command failed, determined from its error messages.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Failure {
+    /// Dependencies could not be resolved.
+    Resolution,
+    /// A dependency had to be downloaded, which isn't possible offline.
+    MissingOffline,
+    /// `Cargo.toml` could not be parsed.
+    ManifestParse,
+    /// The crate uses an edition this cargo doesn't know about.
+    UnsupportedEdition,
+    /// The crate needs an unstable cargo feature.
+    UnstableFeature,
+    Timeout,
+    Other,
+}
+
+impl Failure {
+    /// Classifies a failed command, or returns `None` if it succeeded.
+    pub fn classify(output: &Output) -> Option<Failure> {
+        if output.timed_out {
+            return Some(Failure::Timeout);
+        }
+        if output.status.success() {
+            return None;
+        }
+        let stderr = &output.stderr;
+        let has = |patterns: &[&str]| patterns.iter().any(|p| stderr.contains(p));
+        // Editions and unstable features are checked first since they are
+        // also reported as manifest parse errors. Cargo reminds about
+        // offline mode on every resolution failure, so that can't be used to
+        // tell missing dependencies apart.
+        let failure = if has(&[
+            "supported edition values are",
+            "is older than the `",
+            "failed to parse the `edition` key",
+        ]) {
+            Failure::UnsupportedEdition
+        } else if has(&[
+            "is not stabilized in this version of Cargo",
+            "requires the Cargo feature",
+            "requires a nightly version",
+            "unknown Cargo.toml feature",
+        ]) {
+            Failure::UnstableFeature
+        } else if has(&[
+            "no matching package named",
+            "--offline was specified",
+            "failed to download",
+        ]) {
+            Failure::MissingOffline
+        } else if has(&[
+            "failed to select a version",
+            "cyclic package dependency",
+            "failed to resolve patches",
+        ]) {
+            Failure::Resolution
+        } else if has(&[
+            "failed to parse manifest",
+            "TOML parse error",
+            "could not parse input as TOML",
+            "--> Cargo.toml:",
+        ]) {
+            Failure::ManifestParse
+        } else {
+            Failure::Other
+        };
+        Some(failure)
+    }
+
+    /// Returns the most specific line of cargo's error, which is the
+    /// innermost cause if there is one.
+    pub fn message(output: &Output) -> &str {
+        let stderr = &output.stderr;
+        match stderr.rsplit_once("Caused by:") {
+            Some((_, cause)) => cause.lines().map(str::trim).find(|l| !l.is_empty()),
+            None => stderr.lines().find(|line| line.starts_with("error")),
+        }
+        .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Failure::Resolution => "resolution",
+            Failure::MissingOffline => "missing-offline",
+            Failure::ManifestParse => "manifest-parse",
+            Failure::UnsupportedEdition => "unsupported-edition",
+            Failure::UnstableFeature => "unstable-feature",
+            Failure::Timeout => "timeout",
+            Failure::Other => "other",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Recursively copies a directory, recreating symlinks instead of following
+/// them.
+fn copy_dir(src: &Path, dst: &Path) -> Result<()> {
+    for entry in walkdir::WalkDir::new(src) {
+        let entry = entry?;
+        let rel = entry.path().strip_prefix(src).unwrap();
+        if rel == Path::new(MARKER_FILE) {
+            continue;
+        }
+        let target = dst.join(rel);
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+            #[cfg(not(unix))]
+            fs::copy(entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,273 @@
+//! Differential testing of two cargo binaries.
+//!
+//! A [`Differential`] runs the same cargo command with two [`CargoRunner`]s,
+//! each in its own copy of a crate, normalizes what each of them produced,
+//! and compares the results with a [`Differ`]. This is how a locally built
+//! cargo is checked against a release before a change is merged.
+
+use crate::cargo::{CargoRunner, Workspace};
+use crate::lockfile::{self, Class};
+use crate::process::Output;
+use anyhow::{bail, Context, Result};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What to compare after running a command.
+#[derive(Clone, Debug)]
+pub enum Capture {
+    /// The standard output of cargo.
+    Stdout,
+    /// A file written by cargo, relative to the crate root, such as
+    /// `Cargo.lock`.
+    File(PathBuf),
+}
+
+/// Rewrites output before it is compared, to hide differences that don't
+/// matter.
+pub trait Normalizer: Sync {
+    fn normalize(&self, ws: &Workspace, output: String) -> String;
+}
+
+/// Compares normalized outputs.
+pub trait Differ: Sync {
+    /// Returns the differences between `old` and `new`.
+    fn diff(&self, old: &str, new: &str) -> Result<Diff>;
+}
+
+/// The differences found by a [`Differ`].
+#[derive(Debug, Default)]
+pub struct Diff {
+    /// A description of every difference, empty if the results are
+    /// equivalent.
+    pub changes: Vec<String>,
+    /// How the results differ, for differs that compare lockfiles.
+    pub class: Option<Class>,
+}
+
+/// Replaces the paths of the scratch workspace with `[ROOT]` and `[TARGET]`,
+/// since they are different for every run.
+pub struct ReplacePaths;
+
+impl Normalizer for ReplacePaths {
+    fn normalize(&self, ws: &Workspace, output: String) -> String {
+        output
+            .replace(ws.target_dir().to_str().unwrap(), "[TARGET]")
+            .replace(ws.path().to_str().unwrap(), "[ROOT]")
+    }
+}
+
+/// Sorts lines, for commands whose output order is not meaningful.
+pub struct SortLines;
+
+impl Normalizer for SortLines {
+    fn normalize(&self, _ws: &Workspace, output: String) -> String {
+        let mut lines: Vec<_> = output.lines().collect();
+        lines.sort_unstable();
+        lines.join("\n")
+    }
+}
+
+/// Compares outputs line by line, reporting a unified diff.
+pub struct TextDiff;
+
+impl Differ for TextDiff {
+    fn diff(&self, old: &str, new: &str) -> Result<Diff> {
+        if old == new {
+            return Ok(Diff::default());
+        }
+        let diff = similar::TextDiff::from_lines(old, new);
+        let unified = diff
+            .unified_diff()
+            .context_radius(3)
+            .header("old", "new")
+            .to_string();
+        Ok(Diff {
+            changes: vec![unified],
+            class: None,
+        })
+    }
+}
+
+/// Compares `Cargo.lock` files with [`lockfile::compare`], reporting each
+/// package change and the [`Class`] of the difference.
+///
+/// Falls back to [`TextDiff`] if the lockfiles only differ in formatting.
+pub struct LockfileDiff;
+
+impl Differ for LockfileDiff {
+    fn diff(&self, old: &str, new: &str) -> Result<Diff> {
+        let (class, changes) = lockfile::compare(old, new)?;
+        let changes = match class {
+            Class::Formatting => TextDiff.diff(old, new)?.changes,
+            _ => changes.iter().map(|c| c.to_string()).collect(),
+        };
+        Ok(Diff {
+            changes,
+            class: Some(class),
+        })
+    }
+}
+
+/// The result of running a [`Differential`] on one crate.
+#[derive(Debug)]
+pub enum Outcome {
+    /// Both commands succeeded with equivalent results.
+    Same,
+    /// Both commands failed, usually because the crate can't be built
+    /// offline. Nothing was compared.
+    BothFailed { old: Output, new: Output },
+    /// Only one of the commands succeeded.
+    StatusMismatch { old: Output, new: Output },
+    /// Both commands succeeded, with the given differences between their
+    /// normalized results.
+    Different {
+        old: String,
+        new: String,
+        changes: Vec<String>,
+        /// How they differ, if the [`Differ`] classifies differences.
+        class: Option<Class>,
+    },
+}
+
+impl Outcome {
+    /// Turns anything other than [`Outcome::Same`] or
+    /// [`Outcome::BothFailed`] into an error, so it is reported by the scan.
+    pub fn check(self, crate_dir: &Path) -> Result<()> {
+        match self {
+            Outcome::Same => Ok(()),
+            Outcome::BothFailed { old, .. } => {
+                eprintln!("{crate_dir:?} failed:\n{}\n{}", old.stdout, old.stderr);
+                Ok(())
+            }
+            Outcome::StatusMismatch { old, new } => {
+                let (which, output) = if old.success() {
+                    ("new", new)
+                } else {
+                    ("old", old)
+                };
+                bail!(
+                    "{crate_dir:?} {which} failed:\n{}\n{}",
+                    output.stdout,
+                    output.stderr
+                )
+            }
+            Outcome::Different { changes, .. } => {
+                let mut msg = format!("{crate_dir:?} is different:");
+                for change in changes {
+                    let change = change.trim_end().replace('\n', "\n    ");
+                    write!(msg, "\n    {change}").unwrap();
+                }
+                bail!(msg)
+            }
+        }
+    }
+}
+
+/// Runs a cargo command with two different runners and compares the
+/// results.
+pub struct Differential {
+    old: CargoRunner,
+    new: CargoRunner,
+    args: Vec<String>,
+    capture: Capture,
+    remove: Vec<PathBuf>,
+    normalizers: Vec<Box<dyn Normalizer>>,
+    differ: Box<dyn Differ>,
+}
+
+impl Differential {
+    /// Creates a differential test of `cargo <args>`, comparing stdout with
+    /// [`TextDiff`] after [`ReplacePaths`].
+    pub fn new(old: CargoRunner, new: CargoRunner, args: &[&str]) -> Differential {
+        Differential {
+            old,
+            new,
+            args: args.iter().map(|s| s.to_string()).collect(),
+            capture: Capture::Stdout,
+            remove: Vec::new(),
+            normalizers: vec![Box::new(ReplacePaths)],
+            differ: Box::new(TextDiff),
+        }
+    }
+
+    /// Sets what is compared after the command runs.
+    pub fn capture(mut self, capture: Capture) -> Differential {
+        self.capture = capture;
+        self
+    }
+
+    /// Removes a file from each copy of the crate before running, such as a
+    /// shipped `Cargo.lock` that would otherwise be reused.
+    pub fn remove(mut self, path: impl Into<PathBuf>) -> Differential {
+        self.remove.push(path.into());
+        self
+    }
+
+    /// Adds a normalizer, run after the ones already added.
+    pub fn normalizer(mut self, normalizer: impl Normalizer + 'static) -> Differential {
+        self.normalizers.push(Box::new(normalizer));
+        self
+    }
+
+    /// Sets how the normalized results are compared.
+    pub fn differ(mut self, differ: impl Differ + 'static) -> Differential {
+        self.differ = Box::new(differ);
+        self
+    }
+
+    /// Runs both sides against the extracted crate at `crate_dir`.
+    ///
+    /// A timeout on either side is returned as an error.
+    pub fn run(&self, crate_dir: &Path) -> Result<Outcome> {
+        let (old_ws, old) = self.run_one(&self.old, crate_dir)?;
+        let (new_ws, new) = self.run_one(&self.new, crate_dir)?;
+        match (old.success(), new.success()) {
+            (true, true) => {}
+            (false, false) => return Ok(Outcome::BothFailed { old, new }),
+            _ => return Ok(Outcome::StatusMismatch { old, new }),
+        }
+        let old = self.captured(&old_ws, old)?;
+        let new = self.captured(&new_ws, new)?;
+        let Diff { changes, class } = self.differ.diff(&old, &new)?;
+        if changes.is_empty() {
+            Ok(Outcome::Same)
+        } else {
+            Ok(Outcome::Different {
+                old,
+                new,
+                changes,
+                class,
+            })
+        }
+    }
+
+    fn run_one(&self, runner: &CargoRunner, crate_dir: &Path) -> Result<(Workspace, Output)> {
+        let ws = runner.prepare(crate_dir)?;
+        for path in &self.remove {
+            let path = ws.path().join(path);
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+        let args: Vec<_> = self.args.iter().map(|s| s.as_str()).collect();
+        let output = runner.run(&ws, &args)?;
+        output.check_timeout()?;
+        Ok((ws, output))
+    }
+
+    /// Returns the normalized result of a successful run.
+    fn captured(&self, ws: &Workspace, output: Output) -> Result<String> {
+        let mut result = match &self.capture {
+            Capture::Stdout => output.stdout,
+            Capture::File(path) => {
+                let path = ws.path().join(path);
+                fs::read_to_string(&path).with_context(|| format!("failed to read {path:?}"))?
+            }
+        };
+        for normalizer in &self.normalizers {
+            result = normalizer.normalize(ws, result);
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,540 @@
+//! In-process extraction of `.crate` files.
+//!
+//! Crates on crates.io are untrusted input, so extraction never trusts the
+//! paths or entry types in the archive. Anything that could write outside
+//! of the destination directory, or that could later lead a scanner outside
+//! of it, is skipped and reported instead of being unpacked.
+//!
+//! [`extract_crate_atomic`] additionally makes sure that a crate directory is
+//! either complete or absent. The crate is extracted into a temporary
+//! directory next to its final location, a [`MARKER_FILE`] recording the
+//! checksum of the `.crate` file is written, and only then is it renamed into
+//! place.
+
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, EntryType};
+
+/// File written inside an extracted crate directory once extraction is
+/// complete. It contains the SHA-256 checksum of the `.crate` file.
+pub const MARKER_FILE: &str = ".crates-scanner-extracted";
+
+/// Prefix of the temporary directories used by [`extract_crate_atomic`].
+///
+/// These are only left behind if the process was killed while extracting.
+pub const TEMP_PREFIX: &str = ".extract-";
+
+/// Summary of extracting a single `.crate` file.
+#[derive(Debug, Default)]
+pub struct ExtractReport {
+    /// Number of entries written to disk.
+    pub extracted: usize,
+    /// Entries that were not written because they were unsafe.
+    pub rejected: Vec<Rejected>,
+}
+
+/// An archive entry that was refused during extraction.
+#[derive(Debug)]
+pub struct Rejected {
+    /// The path of the entry as recorded in the archive.
+    pub path: PathBuf,
+    pub reason: RejectReason,
+}
+
+#[derive(Debug)]
+pub enum RejectReason {
+    /// The entry path is absolute.
+    AbsolutePath,
+    /// The entry path contains a `..` component.
+    ParentDir,
+    /// The entry is a character device, block device, or fifo.
+    SpecialFile(EntryType),
+    /// The entry is a symlink or hard link whose target is outside of the
+    /// destination directory.
+    LinkOutsideRoot(PathBuf),
+    /// The entry is a symlink whose target doesn't exist, so it can't be
+    /// verified to stay inside of the destination directory.
+    DanglingLink(PathBuf),
+    /// The entry would be written through a symlink unpacked earlier that
+    /// leads outside of the destination directory, or doesn't resolve.
+    ThroughLink(PathBuf),
+    /// The `tar` crate refused to unpack the entry.
+    Unpack,
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: ", self.path)?;
+        match &self.reason {
+            RejectReason::AbsolutePath => write!(f, "absolute path"),
+            RejectReason::ParentDir => write!(f, "path contains `..`"),
+            RejectReason::SpecialFile(kind) => write!(f, "special file {kind:?}"),
+            RejectReason::LinkOutsideRoot(target) => {
+                write!(f, "link target {target:?} is outside of the root")
+            }
+            RejectReason::DanglingLink(target) => {
+                write!(f, "link target {target:?} does not exist")
+            }
+            RejectReason::ThroughLink(link) => {
+                write!(
+                    f,
+                    "written through {link:?}, which leads outside of the root"
+                )
+            }
+            RejectReason::Unpack => write!(f, "refused by tar"),
+        }
+    }
+}
+
+/// Extracts the `.crate` file at `crate_path` into the directory `dst`.
+///
+/// The archive normally contains a single `name-version/` directory, which
+/// will be created inside of `dst`. Unsafe entries are skipped and listed in
+/// the returned report. An error is only returned if the archive could not
+/// be read or a file could not be written.
+pub fn extract_crate(crate_path: &Path, dst: &Path) -> Result<ExtractReport> {
+    fs::create_dir_all(dst).with_context(|| format!("failed to create {dst:?}"))?;
+    let f = File::open(crate_path).with_context(|| format!("failed to open {crate_path:?}"))?;
+    let mut archive = Archive::new(GzDecoder::new(f));
+    archive.set_preserve_permissions(false);
+    archive.set_preserve_ownerships(false);
+    archive.set_unpack_xattrs(false);
+    let root = dst.canonicalize()?;
+
+    let mut report = ExtractReport::default();
+    let mut links = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let reject = |reason| Rejected {
+            path: path.clone(),
+            reason,
+        };
+        if let Some(reason) = check_path(&path) {
+            report.rejected.push(reject(reason));
+            continue;
+        }
+        let kind = entry.header().entry_type();
+        match kind {
+            EntryType::Char | EntryType::Block | EntryType::Fifo => {
+                report
+                    .rejected
+                    .push(reject(RejectReason::SpecialFile(kind)));
+                continue;
+            }
+            EntryType::Symlink | EntryType::Link => {
+                let target = entry.link_name()?.filter(|t| !t.as_os_str().is_empty());
+                let Some(target) = target else {
+                    report.rejected.push(reject(RejectReason::Unpack));
+                    continue;
+                };
+                // Symlinks are relative to the directory containing them,
+                // hard links are relative to the root of the archive.
+                let base = if kind == EntryType::Symlink {
+                    path.parent().unwrap_or(Path::new(""))
+                } else {
+                    Path::new("")
+                };
+                let target = target.into_owned();
+                if target.is_absolute() || !stays_inside(&base.join(&target)) {
+                    report
+                        .rejected
+                        .push(reject(RejectReason::LinkOutsideRoot(target)));
+                    continue;
+                }
+                if kind == EntryType::Symlink {
+                    links.push((path.clone(), target));
+                } else if let Some(reason) = check_link_on_disk(&root, &target) {
+                    report.rejected.push(reject(reason));
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        // tar refuses these with an error, which would abandon the crate.
+        // A directory entry is checked itself, since tar would otherwise
+        // set the permissions of whatever an existing symlink leads to.
+        let dir = if kind == EntryType::Directory {
+            &path
+        } else {
+            path.parent().unwrap_or(Path::new(""))
+        };
+        if let Some(reason) = check_dir_on_disk(&root, dir) {
+            report.rejected.push(reject(reason));
+            continue;
+        }
+        if entry.unpack_in(dst)? {
+            report.extracted += 1;
+        } else {
+            report.rejected.push(reject(RejectReason::Unpack));
+        }
+    }
+
+    // A chain of symlinks can escape the root even when every link looks
+    // fine on its own (`a -> .` followed by `b -> a/../x`), so check where
+    // each one actually ends up now that everything is on disk.
+    for (path, target) in links {
+        let link = dst.join(&path);
+        let reason = match link.canonicalize() {
+            Ok(resolved) if resolved.starts_with(&root) => continue,
+            Ok(resolved) => RejectReason::LinkOutsideRoot(resolved),
+            Err(_) => RejectReason::DanglingLink(target),
+        };
+        fs::remove_file(&link)?;
+        report.extracted -= 1;
+        report.rejected.push(Rejected { path, reason });
+    }
+    Ok(report)
+}
+
+/// State of a crate directory compared to the `.crate` file it should
+/// have been extracted from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The directory does not exist.
+    Missing,
+    /// The directory exists but has no [`MARKER_FILE`], so extraction was
+    /// interrupted (or it was extracted by something else).
+    Partial,
+    /// The marker's checksum does not match the `.crate` file.
+    Stale,
+    Complete,
+}
+
+/// Checks whether `crate_dir` holds a complete extraction of `crate_path`.
+///
+/// Comparing checksums requires reading the whole `.crate` file, so it is
+/// only done if `verify` is set. Otherwise the presence of the marker is
+/// enough.
+pub fn check_extracted(crate_path: &Path, crate_dir: &Path, verify: bool) -> Result<Status> {
+    if !crate_dir.exists() {
+        return Ok(Status::Missing);
+    }
+    let recorded = match fs::read_to_string(crate_dir.join(MARKER_FILE)) {
+        Ok(r) => r,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Status::Partial),
+        Err(e) => return Err(e.into()),
+    };
+    if verify && recorded.trim() != checksum(crate_path)? {
+        return Ok(Status::Stale);
+    }
+    Ok(Status::Complete)
+}
+
+/// Extracts `crate_path` so that its `name-version/` directory ends up at
+/// `crate_dir`, replacing anything that was there.
+///
+/// `crate_dir` is never observed in a partially extracted state. Entries
+/// outside of the `name-version/` directory are discarded.
+pub fn extract_crate_atomic(crate_path: &Path, crate_dir: &Path) -> Result<ExtractReport> {
+    let parent = crate_dir.parent().unwrap();
+    let dir_name = crate_dir.file_name().unwrap();
+    fs::create_dir_all(parent).with_context(|| format!("failed to create {parent:?}"))?;
+    let sum = checksum(crate_path)?;
+    let tmp = tempfile::Builder::new()
+        .prefix(TEMP_PREFIX)
+        .tempdir_in(parent)
+        .with_context(|| format!("failed to create temp directory in {parent:?}"))?;
+    let report = extract_crate(crate_path, tmp.path())?;
+    // The directory in the archive uses the name as it was published, which
+    // may differ in case from the name used for `crate_dir`.
+    let dir_name = dir_name.to_str().unwrap();
+    let extracted = fs::read_dir(tmp.path())?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .find(|path| {
+            path.is_dir()
+                && path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.eq_ignore_ascii_case(dir_name))
+        });
+    let Some(extracted) = extracted else {
+        bail!("{crate_path:?} does not contain a {dir_name:?} directory");
+    };
+    fs::write(extracted.join(MARKER_FILE), format!("{sum}\n"))?;
+    if crate_dir.exists() {
+        fs::remove_dir_all(crate_dir)
+            .with_context(|| format!("failed to remove old {crate_dir:?}"))?;
+    }
+    fs::rename(&extracted, crate_dir)
+        .with_context(|| format!("failed to rename {extracted:?} to {crate_dir:?}"))?;
+    Ok(report)
+}
+
+/// Removes temporary directories left behind in `dir` by an interrupted
+/// [`extract_crate_atomic`], returning how many were removed.
+pub fn remove_temp_dirs(dir: &Path) -> Result<usize> {
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with(TEMP_PREFIX) {
+            fs::remove_dir_all(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Returns the hex SHA-256 checksum of a file, as used in the index.
+pub fn checksum(path: &Path) -> Result<String> {
+    let mut f = File::open(path).with_context(|| format!("failed to open {path:?}"))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut f, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn check_path(path: &Path) -> Option<RejectReason> {
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return Some(RejectReason::AbsolutePath),
+            Component::ParentDir => return Some(RejectReason::ParentDir),
+            Component::CurDir | Component::Normal(_) => {}
+        }
+    }
+    None
+}
+
+/// Checks that the directory `dir`, relative to `root` (the canonical
+/// destination), resolves inside of it, following the symlinks unpacked so
+/// far. Directories that don't exist yet will be created in their nearest
+/// existing ancestor, so that is what's checked.
+fn check_dir_on_disk(root: &Path, dir: &Path) -> Option<RejectReason> {
+    let mut existing = root.join(dir);
+    while existing.symlink_metadata().is_err() {
+        existing.pop();
+    }
+    match existing.canonicalize() {
+        Ok(resolved) if resolved.starts_with(root) => None,
+        _ => {
+            let link = existing.strip_prefix(root).unwrap_or(&existing);
+            Some(RejectReason::ThroughLink(link.to_path_buf()))
+        }
+    }
+}
+
+/// Checks that the target of a hard link, relative to `root`, exists and
+/// resolves inside of it.
+fn check_link_on_disk(root: &Path, target: &Path) -> Option<RejectReason> {
+    match root.join(target).canonicalize() {
+        Ok(resolved) if resolved.starts_with(root) => None,
+        Ok(resolved) => Some(RejectReason::LinkOutsideRoot(resolved)),
+        Err(_) => Some(RejectReason::DanglingLink(target.to_path_buf())),
+    }
+}
+
+/// Returns whether the relative path `path` stays below its starting point
+/// after resolving `..` components lexically.
+fn stays_inside(path: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return false,
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            },
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tar::Header;
+
+    enum Entry<'a> {
+        File(&'a str),
+        Dir,
+        Special(EntryType),
+        Symlink(&'a str),
+        HardLink(&'a str),
+    }
+
+    /// Writes a `.crate` file into `dir`. Headers are filled in by hand,
+    /// since `tar::Builder` refuses to write unsafe paths.
+    fn write_crate(dir: &Path, entries: &[(&str, Entry<'_>)]) -> PathBuf {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, entry) in entries {
+            let mut header = Header::new_ustar();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_mode(0o644);
+            let (kind, data, link) = match entry {
+                Entry::File(data) => (EntryType::Regular, data.as_bytes(), None),
+                Entry::Dir => (EntryType::Directory, &b""[..], None),
+                Entry::Special(kind) => (*kind, &b""[..], None),
+                Entry::Symlink(target) => (EntryType::Symlink, &b""[..], Some(target)),
+                Entry::HardLink(target) => (EntryType::Link, &b""[..], Some(target)),
+            };
+            header.set_entry_type(kind);
+            if let Some(link) = link {
+                header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+            }
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        let bytes = builder.into_inner().unwrap().finish().unwrap();
+        let path = dir.join("pkg-0.1.0.crate");
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// Extracts `entries` into `base/dst`, next to `base/outside` which has
+    /// a `secret` file, and checks that nothing outside of `dst` changed.
+    /// Returns the report and its rejected entries with their reasons.
+    fn extract(entries: &[(&str, Entry<'_>)]) -> (ExtractReport, Vec<(String, String)>) {
+        let base = tempfile::tempdir().unwrap();
+        let outside = base.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        let crate_path = write_crate(base.path(), entries);
+        let dst = base.path().join("dst");
+
+        let report = extract_crate(&crate_path, &dst).unwrap();
+
+        let mut names: Vec<_> = fs::read_dir(base.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["dst", "outside", "pkg-0.1.0.crate"]);
+        let outside_names: Vec<_> = fs::read_dir(&outside)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(outside_names, ["secret"]);
+        assert_eq!(
+            fs::read_to_string(outside.join("secret")).unwrap(),
+            "secret"
+        );
+        for entry in walkdir::WalkDir::new(&dst) {
+            let entry = entry.unwrap();
+            if entry.path_is_symlink() {
+                let resolved = entry.path().canonicalize().unwrap();
+                assert!(resolved.starts_with(dst.canonicalize().unwrap()));
+            }
+        }
+
+        let rejected = report
+            .rejected
+            .iter()
+            .map(|r| (r.path.to_str().unwrap().to_string(), reason(&r.reason)))
+            .collect();
+        (report, rejected)
+    }
+
+    fn reason(reason: &RejectReason) -> String {
+        match reason {
+            RejectReason::AbsolutePath => "absolute",
+            RejectReason::ParentDir => "parent dir",
+            RejectReason::SpecialFile(_) => "special",
+            RejectReason::LinkOutsideRoot(_) => "outside",
+            RejectReason::DanglingLink(_) => "dangling",
+            RejectReason::ThroughLink(_) => "through link",
+            RejectReason::Unpack => "unpack",
+        }
+        .to_string()
+    }
+
+    fn rejected(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|(path, reason)| (path.to_string(), reason.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn unsafe_paths() {
+        let (report, rejected_entries) = extract(&[
+            ("pkg-0.1.0/src/lib.rs", Entry::File("fn f() {}")),
+            ("../outside/pwned", Entry::File("x")),
+            ("pkg-0.1.0/../../outside/pwned", Entry::File("x")),
+            ("/tmp/pwned", Entry::File("x")),
+        ]);
+        assert_eq!(report.extracted, 1);
+        assert_eq!(
+            rejected_entries,
+            rejected(&[
+                ("../outside/pwned", "parent dir"),
+                ("pkg-0.1.0/../../outside/pwned", "parent dir"),
+                ("/tmp/pwned", "absolute"),
+            ])
+        );
+    }
+
+    #[test]
+    fn special_files() {
+        let (report, rejected_entries) = extract(&[
+            ("pkg-0.1.0/fifo", Entry::Special(EntryType::Fifo)),
+            ("pkg-0.1.0/char", Entry::Special(EntryType::Char)),
+            ("pkg-0.1.0/block", Entry::Special(EntryType::Block)),
+        ]);
+        assert_eq!(report.extracted, 0);
+        assert_eq!(
+            rejected_entries,
+            rejected(&[
+                ("pkg-0.1.0/fifo", "special"),
+                ("pkg-0.1.0/char", "special"),
+                ("pkg-0.1.0/block", "special"),
+            ])
+        );
+    }
+
+    #[test]
+    fn links_out_of_the_root() {
+        let (report, rejected_entries) = extract(&[
+            ("pkg-0.1.0/src/lib.rs", Entry::File("fn f() {}")),
+            ("pkg-0.1.0/inside", Entry::Symlink("src/lib.rs")),
+            ("pkg-0.1.0/up", Entry::Symlink("../../outside")),
+            ("pkg-0.1.0/abs", Entry::Symlink("/etc")),
+            ("pkg-0.1.0/hard", Entry::HardLink("../outside/secret")),
+            ("pkg-0.1.0/missing", Entry::Symlink("nothing")),
+        ]);
+        assert_eq!(report.extracted, 2);
+        assert_eq!(
+            rejected_entries,
+            rejected(&[
+                ("pkg-0.1.0/up", "outside"),
+                ("pkg-0.1.0/abs", "outside"),
+                ("pkg-0.1.0/hard", "outside"),
+                ("pkg-0.1.0/missing", "dangling"),
+            ])
+        );
+    }
+
+    #[test]
+    fn chained_symlinks() {
+        // Each link stays inside on its own, but `b` goes through `a`, which
+        // is `pkg-0.1.0` itself, so `b` is `dst/../outside`.
+        let (report, rejected_entries) = extract(&[
+            ("pkg-0.1.0/a", Entry::Symlink(".")),
+            ("pkg-0.1.0/b", Entry::Symlink("a/../../outside")),
+            ("pkg-0.1.0/b/pwned", Entry::File("x")),
+            ("pkg-0.1.0/b/", Entry::Dir),
+            ("pkg-0.1.0/hard", Entry::HardLink("pkg-0.1.0/b/secret")),
+            ("pkg-0.1.0/src/lib.rs", Entry::File("fn f() {}")),
+        ]);
+        // `a` and `lib.rs`; `b` was unpacked but then removed.
+        assert_eq!(report.extracted, 2);
+        assert_eq!(
+            rejected_entries,
+            rejected(&[
+                ("pkg-0.1.0/b/pwned", "through link"),
+                ("pkg-0.1.0/b/", "through link"),
+                ("pkg-0.1.0/hard", "outside"),
+                ("pkg-0.1.0/b", "outside"),
+            ])
+        );
+    }
+}
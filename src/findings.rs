@@ -0,0 +1,202 @@
+//! Locating findings in source files and showing them with context.
+//!
+//! A [`Finding`] has a [`Span`] made either from byte offsets or from a
+//! `proc_macro2::Span` (which needs the `span-locations` feature, enabled by
+//! this crate). [`Finding::render`] prints it like a rustc diagnostic, with
+//! the source lines and the span underlined:
+//!
+//! ```text
+//! string continuation followed by an empty line
+//!   --> foo-0.1.0/src/lib.rs:3:15
+//!   |
+//! 3 |     let s = "a\
+//!   |               ^
+//! 4 |
+//!   | ^
+//! ```
+
+use serde_json::json;
+use std::fmt::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// The most source lines shown for one finding.
+const MAX_LINES: usize = 6;
+
+/// A position in a file, like `proc_macro2::LineColumn`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineColumn {
+    /// The line, starting at 1.
+    pub line: usize,
+    /// The column in characters, starting at 0.
+    pub column: usize,
+}
+
+impl LineColumn {
+    /// Finds the line and column of the byte `offset` in `contents`.
+    pub fn from_offset(contents: &str, offset: usize) -> LineColumn {
+        let before = &contents[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        LineColumn {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count(),
+        }
+    }
+
+    /// The byte offset of this position in `contents`, clamped to the end
+    /// of its line.
+    pub fn offset(&self, contents: &str) -> usize {
+        let line_start = if self.line <= 1 {
+            0
+        } else {
+            contents
+                .match_indices('\n')
+                .nth(self.line - 2)
+                .map_or(contents.len(), |(i, _)| i + 1)
+        };
+        let line = &contents[line_start..];
+        let line = &line[..line.find('\n').unwrap_or(line.len())];
+        line_start
+            + line
+                .char_indices()
+                .nth(self.column)
+                .map_or(line.len(), |(i, _)| i)
+    }
+}
+
+/// A region of a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: LineColumn,
+    /// The position just after the region.
+    pub end: LineColumn,
+}
+
+impl Span {
+    /// Makes a span from the byte range `start..end` of `contents`.
+    pub fn from_offsets(contents: &str, start: usize, end: usize) -> Span {
+        Span {
+            start: LineColumn::from_offset(contents, start),
+            end: LineColumn::from_offset(contents, end),
+        }
+    }
+
+    /// The byte range of this span in `contents`.
+    pub fn byte_range(&self, contents: &str) -> Range<usize> {
+        self.start.offset(contents)..self.end.offset(contents)
+    }
+}
+
+impl From<proc_macro2::Span> for Span {
+    fn from(span: proc_macro2::Span) -> Span {
+        let (start, end) = (span.start(), span.end());
+        Span {
+            start: LineColumn {
+                line: start.line,
+                column: start.column,
+            },
+            end: LineColumn {
+                line: end.line,
+                column: end.column,
+            },
+        }
+    }
+}
+
+/// Something a scanner found at a place in a file.
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub path: PathBuf,
+    pub span: Span,
+    pub message: String,
+}
+
+impl Finding {
+    /// Creates a finding at `span` in the file at `path`.
+    pub fn new(
+        path: impl Into<PathBuf>,
+        span: impl Into<Span>,
+        message: impl Into<String>,
+    ) -> Finding {
+        Finding {
+            path: path.into(),
+            span: span.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Formats the finding with an excerpt of `contents`, the file it was
+    /// found in.
+    pub fn render(&self, contents: &str) -> String {
+        let Span { start, mut end } = self.span;
+        // A span ending with a newline ends at the start of the next line,
+        // which shouldn't be shown.
+        if end.column == 0 && end.line > start.line {
+            end.line -= 1;
+            end.column = usize::MAX;
+        }
+        let mut out = format!(
+            "{}\n  --> {}:{}:{}\n",
+            self.message,
+            self.path.display(),
+            start.line,
+            start.column + 1
+        );
+        let lines: Vec<_> = contents.split('\n').collect();
+        let last = end.line.max(start.line).min(lines.len());
+        let mut shown: Vec<usize> = (start.line..=last).collect();
+        if shown.len() > MAX_LINES {
+            shown.drain(MAX_LINES / 2..shown.len() - MAX_LINES / 2);
+        }
+        let width = last.to_string().len();
+        let _ = writeln!(out, "{:width$} |", "");
+        let mut previous = None;
+        for line in shown {
+            if previous.is_some_and(|p| p + 1 != line) {
+                let _ = writeln!(out, "{:width$} ...", "");
+            }
+            previous = Some(line);
+            let text = lines[line - 1].trim_end_matches('\r');
+            let from = if line == start.line { start.column } else { 0 };
+            let to = if line == end.line {
+                end.column.min(text.chars().count())
+            } else {
+                text.chars().count()
+            };
+            let _ = writeln!(out, "{line:>width$} | {}", expand_tabs(text));
+            // Underline in display columns, so tabs line up.
+            let before: String = text.chars().take(from).collect();
+            let marked: String = text
+                .chars()
+                .skip(from)
+                .take(to.saturating_sub(from))
+                .collect();
+            let _ = writeln!(
+                out,
+                "{:width$} | {}{}",
+                "",
+                " ".repeat(expand_tabs(&before).chars().count()),
+                "^".repeat(expand_tabs(&marked).chars().count().max(1))
+            );
+        }
+        out
+    }
+
+    /// The finding as a JSON object for a [`crate::results::Sink`], with the
+    /// rendered excerpt.
+    pub fn to_json(&self, contents: &str) -> serde_json::Value {
+        json!({
+            "path": self.path,
+            "line": self.span.start.line,
+            "column": self.span.start.column + 1,
+            "end_line": self.span.end.line,
+            "end_column": self.span.end.column + 1,
+            "message": self.message,
+            "excerpt": self.render(contents),
+        })
+    }
+}
+
+fn expand_tabs(s: &str) -> String {
+    s.replace('\t', "    ")
+}
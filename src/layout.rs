@@ -0,0 +1,170 @@
+//! Directory layouts for extracted crates.
+
+use crate::CrateId;
+use anyhow::{bail, Context, Result};
+use rayon::prelude::*;
+use semver::Version;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How extracted crates are arranged under a root directory.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// The same sharding as the crates.io index and the crates mirror, with
+    /// every version of a crate in a directory named after the crate, such
+    /// as `se/rd/serde/serde-1.0.0/` or `3/s/syn/syn-2.0.0/`.
+    Sharded,
+    /// Every crate directly in the root, such as `serde-1.0.0/`.
+    Flat,
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Layout> {
+        match s {
+            "sharded" => Ok(Layout::Sharded),
+            "flat" => Ok(Layout::Flat),
+            _ => bail!("unknown layout `{s}`, expected `sharded` or `flat`"),
+        }
+    }
+}
+
+impl Layout {
+    /// Guesses the layout of an existing directory of extracted crates.
+    ///
+    /// A flat layout has `name-version` directories at the top level, which
+    /// can't be confused with shard directories.
+    pub fn detect(root: &Path) -> Result<Layout> {
+        let read_error = || format!("failed to read {root:?}");
+        for entry in read_dir(root).with_context(read_error)? {
+            let name = entry.with_context(read_error)?.file_name();
+            if name.to_str().and_then(parse_dir_name).is_some() {
+                return Ok(Layout::Flat);
+            }
+        }
+        Ok(Layout::Sharded)
+    }
+
+    /// Returns the directory where the given crate is extracted.
+    pub fn crate_dir(&self, root: &Path, id: &CrateId) -> PathBuf {
+        let dir_name = format!("{}-{}", id.name, id.version);
+        match self {
+            Layout::Sharded => root.join(shard(&id.name)).join(&id.name).join(dir_name),
+            Layout::Flat => root.join(dir_name),
+        }
+    }
+
+    /// Finds every extracted crate under `root`.
+    ///
+    /// Hidden entries (such as temporary extraction directories) and
+    /// anything that doesn't fit the layout are skipped, with a warning for
+    /// the latter.
+    pub fn enumerate(&self, root: &Path) -> Result<Vec<(CrateId, PathBuf)>> {
+        let mut crates: Vec<_> = match self {
+            Layout::Sharded => subdirs(root)?
+                .into_par_iter()
+                .map(|shard| {
+                    // `1/` and `2/` contain crate directories directly,
+                    // everything else has a second level of sharding.
+                    let name = shard.file_name().unwrap();
+                    if name == "1" || name == "2" {
+                        subdirs(&shard)
+                    } else {
+                        let mut name_dirs = Vec::new();
+                        for dir in subdirs(&shard)? {
+                            name_dirs.extend(subdirs(&dir)?);
+                        }
+                        Ok(name_dirs)
+                    }
+                })
+                .collect::<Result<Vec<_>>>()?
+                .into_par_iter()
+                .flatten()
+                .map(|name_dir| {
+                    let name = name_dir.file_name().unwrap().to_str().unwrap();
+                    Ok(subdirs(&name_dir)?
+                        .into_iter()
+                        .filter_map(|dir| {
+                            let dir_name = dir.file_name().unwrap().to_str().unwrap();
+                            let id = dir_name
+                                .strip_prefix(name)
+                                .and_then(|v| v.strip_prefix('-'))
+                                .and_then(|v| Version::parse(v).ok())
+                                .map(|version| CrateId {
+                                    name: name.to_string(),
+                                    version,
+                                });
+                            self.check(root, id, dir)
+                        })
+                        .collect::<Vec<_>>())
+                })
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .flatten()
+                .collect(),
+            Layout::Flat => subdirs(root)?
+                .into_par_iter()
+                .filter_map(|dir| {
+                    let id = parse_dir_name(dir.file_name().unwrap().to_str().unwrap());
+                    self.check(root, id, dir)
+                })
+                .collect(),
+        };
+        crates.sort();
+        Ok(crates)
+    }
+
+    fn check(&self, root: &Path, id: Option<CrateId>, dir: PathBuf) -> Option<(CrateId, PathBuf)> {
+        match id {
+            Some(id) if self.crate_dir(root, &id) == dir => Some((id, dir)),
+            _ => {
+                eprintln!("warning: {dir:?} does not match the {self:?} layout, skipping");
+                None
+            }
+        }
+    }
+}
+
+/// Returns the path of a crate's file in the crates.io index, relative to
+/// the root of the index.
+///
+/// The crates mirror uses the same sharding for its directories.
+pub fn shard(name: &str) -> PathBuf {
+    let lower = name.to_lowercase();
+    match lower.len() {
+        1 => PathBuf::from("1"),
+        2 => PathBuf::from("2"),
+        3 => Path::new("3").join(&lower[..1]),
+        _ => Path::new(&lower[..2]).join(&lower[2..4]),
+    }
+}
+
+/// Splits a `name-version` directory name.
+///
+/// Crate names may contain `-`, so this uses the first `-` that is followed
+/// by a valid version.
+pub fn parse_dir_name(dir_name: &str) -> Option<CrateId> {
+    dir_name.match_indices('-').find_map(|(i, _)| {
+        let version = Version::parse(&dir_name[i + 1..]).ok()?;
+        Some(CrateId {
+            name: dir_name[..i].to_string(),
+            version,
+        })
+    })
+}
+
+/// Lists the non-hidden subdirectories of `dir`.
+fn subdirs(dir: &Path) -> Result<Vec<PathBuf>> {
+    let read_error = || format!("failed to read {dir:?}");
+    let mut dirs = Vec::new();
+    for entry in read_dir(dir).with_context(read_error)? {
+        let entry = entry.with_context(read_error)?;
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && entry.file_type().with_context(read_error)?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
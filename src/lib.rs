@@ -1,37 +1,105 @@
-use anyhow::Result;
+use anyhow::{format_err, Result};
 use flate2::read::GzDecoder;
 use rayon::prelude::*;
 use semver::Version;
-use std::collections::hash_map::{Entry, HashMap};
-use std::fs::{File, read_dir};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicU32, Ordering};
 use tar::Archive;
 
+pub mod ast;
+pub mod cargo;
+pub mod differential;
+pub mod extract;
+pub mod findings;
+pub mod layout;
+pub mod literal;
+pub mod lockfile;
+pub mod manifest;
+pub mod modules;
+pub mod parser_diff;
+pub mod pattern;
+pub mod process;
+pub mod reduce;
+pub mod results;
+pub mod selection;
+
+pub use layout::Layout;
+pub use selection::{Selection, Versions};
+
 const ERROR: &str = "\x1b[1m\x1b[38;5;9merror\x1b[0m";
 
-#[derive(Copy, Clone)]
-pub enum Versions {
-    All,
-    Latest,
+/// The name and version of a crate.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CrateId {
+    pub name: String,
+    pub version: Version,
+}
+
+impl CrateId {
+    /// Determines the crate from the path of a `.crate` file in a crates
+    /// mirror, where the file is in a directory named after the crate.
+    pub fn from_crate_path(path: &Path) -> Option<CrateId> {
+        let name = path.parent()?.file_name()?.to_str()?;
+        let file_name = path.file_name()?.to_str()?;
+        let version = file_name
+            .strip_suffix(".crate")?
+            .get(name.len()..)?
+            .strip_prefix('-')?;
+        Some(CrateId {
+            name: name.to_string(),
+            version: Version::parse(version).ok()?,
+        })
+    }
+}
+
+/// Names the crate a scanner is given, either a `.crate` file or an
+/// extracted crate directory, like `serde@1.0.0`.
+pub fn crate_name(crate_path: &Path) -> String {
+    let file_name = crate_path.file_name().unwrap().to_string_lossy();
+    CrateId::from_crate_path(crate_path)
+        .or_else(|| layout::parse_dir_name(&file_name))
+        .map_or_else(|| file_name.to_string(), |id| id.to_string())
+}
+
+impl fmt::Display for CrateId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.name, self.version)
+    }
+}
+
+impl FromStr for CrateId {
+    type Err = anyhow::Error;
+
+    /// Parses `name@version`.
+    fn from_str(s: &str) -> Result<CrateId> {
+        let (name, version) = s
+            .split_once('@')
+            .ok_or_else(|| format_err!("expected `name@version`, got `{s}`"))?;
+        Ok(CrateId {
+            name: name.to_string(),
+            version: Version::parse(version)
+                .map_err(|e| format_err!("invalid version in `{s}`: {e}"))?,
+        })
+    }
 }
 
 /// Scans compressed `.crate` files.
 pub fn scan_compressed<Filt, Scan>(
     crates_path: &Path,
-    versions: Versions,
+    selection: impl Into<Selection>,
     filter: Filt,
     scanner: Scan,
 ) where
     Filt: Fn(&Path) -> bool + Sync,
     Scan: Fn(&Path, &Path, &str) -> Result<()> + Sync,
 {
-    let paths = match versions {
-        Versions::All => collect_all_crates(crates_path),
-        Versions::Latest => collect_latest_crates(crates_path),
-    };
+    let paths = selection.into().collect(crates_path);
     eprintln!("scanning {} crates", paths.len());
 
     let scanned = AtomicU32::new(0);
@@ -81,6 +149,11 @@ pub fn scan_compressed<Filt, Scan>(
                 }
             }
         }
+        // With span-locations, proc-macro2 keeps the source of every file
+        // tokenized on this thread, addressed by 32-bit offsets. Findings
+        // keep line and column `findings::Span`s instead, so no spans
+        // outlive the crate and its sources can be dropped.
+        proc_macro2::extra::invalidate_current_thread_spans();
     });
     println!(
         "load errors: {}\n\
@@ -94,121 +167,278 @@ pub fn scan_compressed<Filt, Scan>(
 
 /// Scans uncompressed crates.
 ///
-/// Crates must be first extracted with the extract-latest tool.
-pub fn scan_uncompressed<Scan>(src_path: &Path, scanner: Scan)
+/// Crates must be first extracted with the extract-latest tool. The
+/// [`Layout`] of `src_path` is detected automatically, and crates without a
+/// completion marker are skipped since they may be incomplete. Fails if
+/// `src_path` can't be read.
+pub fn scan_uncompressed<Scan>(src_path: &Path, scanner: Scan) -> Result<()>
 where
     Scan: Fn(&Path) -> Result<()> + Sync,
 {
-    let paths = collect_uncompressed_paths(src_path);
+    let paths = collect_uncompressed_paths(src_path)?;
     let total = AtomicU32::new(0);
     let scan_errors = AtomicU32::new(0);
+    let timeouts = AtomicU32::new(0);
     eprintln!("scanning {} crates", paths.len());
     paths.par_iter().for_each(|path| {
         let progress = total.fetch_add(1, Ordering::SeqCst);
-        if progress % 10000 == 0 {
+        if progress.is_multiple_of(10000) {
             eprintln!("processed {progress}/{}", paths.len());
         }
-        if let Err(e) = scanner(&path) {
-            eprintln!("{ERROR} scanning {path:?}: {e:?}");
-            scan_errors.fetch_add(1, Ordering::SeqCst);
+        if let Err(e) = scanner(path) {
+            if process::is_timeout(&e) {
+                eprintln!("{ERROR} scanning {path:?}: {e}");
+                timeouts.fetch_add(1, Ordering::SeqCst);
+            } else {
+                eprintln!("{ERROR} scanning {path:?}: {e:?}");
+                scan_errors.fetch_add(1, Ordering::SeqCst);
+            }
         }
+        // See `scan_compressed`.
+        proc_macro2::extra::invalidate_current_thread_spans();
     });
     println!(
         "scan errors: {}\n\
+        timeouts: {}\n\
         total: {}",
         scan_errors.load(Ordering::SeqCst),
+        timeouts.load(Ordering::SeqCst),
         paths.len()
     );
+    Ok(())
 }
 
-fn collect_uncompressed_paths(src_path: &Path) -> Vec<PathBuf> {
-    read_dir(src_path)
-        .unwrap()
-        .par_bridge()
-        .flat_map(|entry| {
-            let entry = entry.unwrap();
-            let file_name = entry.file_name();
-            read_dir(src_path.join(&file_name))
-                .unwrap()
-                .par_bridge()
-                .flat_map(move |entry| {
-                    read_dir(entry.unwrap().path())
-                        .unwrap()
-                        .flat_map(|entry| {
-                            if file_name == "1" || file_name == "2" {
-                                vec![entry.unwrap().path()]
-                            } else {
-                                read_dir(entry.unwrap().path())
-                                    .unwrap()
-                                    .map(|entry| entry.unwrap().path())
-                                    .collect()
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                })
-        })
-        .collect()
+/// Scans the files of uncompressed crates.
+///
+/// This is the same as [`scan_compressed`], but reads crates extracted with
+/// the extract-latest tool, which is much faster than decompressing every
+/// `.crate` again. The first argument to `scanner` is the extracted crate
+/// directory, and `entry_path` is relative to its parent so that it starts
+/// with `name-version/` just like a path inside of a `.crate` file. Like
+/// [`scan_uncompressed`], this fails if `src_path` can't be read.
+pub fn scan_uncompressed_files<Filt, Scan>(
+    src_path: &Path,
+    filter: Filt,
+    scanner: Scan,
+) -> Result<()>
+where
+    Filt: Fn(&Path) -> bool + Sync,
+    Scan: Fn(&Path, &Path, &str) -> Result<()> + Sync,
+{
+    let paths = collect_uncompressed_paths(src_path)?;
+    eprintln!("scanning {} crates", paths.len());
+
+    let scanned = AtomicU32::new(0);
+    let load_errors = AtomicU32::new(0);
+    let scan_errors = AtomicU32::new(0);
+
+    paths.par_iter().for_each(|crate_path| {
+        let base = crate_path.parent().unwrap();
+        let walker = walkdir::WalkDir::new(crate_path).sort_by_file_name();
+        for entry in walker {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => {
+                    load_errors.fetch_add(1, Ordering::SeqCst);
+                    eprintln!("entry error {crate_path:?}: {e}");
+                    break;
+                }
+            };
+            if !entry.file_type().is_file() || entry.file_name() == extract::MARKER_FILE {
+                continue;
+            }
+            let entry_path = entry.path().strip_prefix(base).unwrap();
+            if filter(entry_path) {
+                let contents = match std::fs::read_to_string(entry.path()) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        load_errors.fetch_add(1, Ordering::SeqCst);
+                        eprintln!("decode error {entry_path:?}: {e}");
+                        break;
+                    }
+                };
+                let progress = scanned.fetch_add(1, Ordering::SeqCst);
+                if progress.is_multiple_of(10000) {
+                    eprintln!("processed {progress}");
+                }
+
+                if let Err(e) = scanner(crate_path, entry_path, &contents) {
+                    eprintln!(
+                        "{ERROR} scanning {entry_path:?}: {e:?}\n\
+                            contents:\n{contents}"
+                    );
+                    scan_errors.fetch_add(1, Ordering::SeqCst);
+                    break;
+                }
+            }
+        }
+        // See `scan_compressed`.
+        proc_macro2::extra::invalidate_current_thread_spans();
+    });
+    println!(
+        "load errors: {}\n\
+        scan errors: {}\n\
+        total: {}",
+        load_errors.load(Ordering::SeqCst),
+        scan_errors.load(Ordering::SeqCst),
+        paths.len()
+    );
+    Ok(())
 }
 
-fn collect_all_crates(crates_path: &Path) -> Vec<PathBuf> {
-    walkdir::WalkDir::new(crates_path)
-        .into_iter()
-        .filter_map(|entry| {
-            let e = entry.unwrap();
-            if e.file_name().to_str().unwrap().ends_with(".crate") {
-                Some(e.path().to_path_buf())
-            } else {
-                None
+/// The files of a crate, read in one pass for scanners that look at all of
+/// them together, like to build its [`modules::CrateFiles`].
+pub struct CrateSources {
+    /// The `.crate` file or extracted crate directory.
+    pub path: PathBuf,
+    /// Every file in the crate, starting with its `name-version/` directory.
+    pub files: Vec<PathBuf>,
+    /// The contents of the files accepted by the filter. Files that aren't
+    /// UTF-8 are left out.
+    pub sources: BTreeMap<PathBuf, String>,
+}
+
+impl CrateSources {
+    /// Reads a `.crate` file or an extracted crate directory, keeping the
+    /// contents of the files `filter` accepts.
+    pub fn read(crate_path: &Path, filter: impl Fn(&Path) -> bool) -> Result<CrateSources> {
+        let mut files = Vec::new();
+        let mut sources = BTreeMap::new();
+        let mut add = |path: PathBuf, read: &mut dyn FnMut() -> std::io::Result<String>| {
+            if filter(&path) {
+                match read() {
+                    Ok(contents) => {
+                        sources.insert(path.clone(), contents);
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {}
+                    Err(e) => return Err(e),
+                }
             }
+            files.push(path);
+            Ok(())
+        };
+        if crate_path.is_dir() {
+            let base = crate_path.parent().unwrap();
+            for entry in walkdir::WalkDir::new(crate_path).sort_by_file_name() {
+                let entry = entry?;
+                if !entry.file_type().is_file() || entry.file_name() == extract::MARKER_FILE {
+                    continue;
+                }
+                let path = entry.path().strip_prefix(base)?.to_path_buf();
+                add(path, &mut || std::fs::read_to_string(entry.path()))?;
+            }
+        } else {
+            let mut archive = Archive::new(GzDecoder::new(File::open(crate_path)?));
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let path = entry.path()?.to_path_buf();
+                add(path, &mut || {
+                    let mut s = String::new();
+                    entry.read_to_string(&mut s)?;
+                    Ok(s)
+                })?;
+            }
+        }
+        Ok(CrateSources {
+            path: crate_path.to_path_buf(),
+            files,
+            sources,
         })
-        .collect()
+    }
 }
 
-pub fn collect_latest_crates(crates_path: &Path) -> Vec<PathBuf> {
-    let mut versions = HashMap::new();
+/// Scans compressed `.crate` files a whole crate at a time.
+///
+/// This is for scanners that need more than one file of a crate, like its
+/// manifest to know what each `.rs` file is compiled as. Each crate is
+/// decompressed once and `scanner` is given the contents of every file
+/// `filter` accepts.
+pub fn scan_compressed_crates<Filt, Scan>(
+    crates_path: &Path,
+    selection: impl Into<Selection>,
+    filter: Filt,
+    scanner: Scan,
+) where
+    Filt: Fn(&Path) -> bool + Sync,
+    Scan: Fn(&CrateSources) -> Result<()> + Sync,
+{
+    scan_crates(&selection.into().collect(crates_path), filter, scanner);
+}
 
-    walkdir::WalkDir::new(crates_path)
-        .into_iter()
-        .filter(|entry| {
-            entry
-                .as_ref()
-                .unwrap()
-                .file_name()
-                .to_str()
-                .unwrap()
-                .ends_with(".crate")
-        })
-        .for_each(|entry| {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            let crate_name = path
-                .parent()
-                .unwrap()
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string();
-            let file_name = path.file_name().unwrap().to_str().unwrap();
-            let version = &file_name[crate_name.len() + 1..file_name.len() - 6];
-            let version = Version::parse(version).unwrap();
-            let rel_path = path.strip_prefix(&crates_path).unwrap();
-            match versions.entry(crate_name.clone()) {
-                Entry::Vacant(e) => {
-                    e.insert((rel_path.to_owned(), version));
-                }
-                Entry::Occupied(mut e) => {
-                    if version > e.get().1 {
-                        e.insert((rel_path.to_owned(), version));
-                    }
+/// Scans uncompressed crates a whole crate at a time. This is the same as
+/// [`scan_compressed_crates`], but reads crates extracted with the
+/// extract-latest tool, and fails if `src_path` can't be read.
+pub fn scan_uncompressed_crates<Filt, Scan>(
+    src_path: &Path,
+    filter: Filt,
+    scanner: Scan,
+) -> Result<()>
+where
+    Filt: Fn(&Path) -> bool + Sync,
+    Scan: Fn(&CrateSources) -> Result<()> + Sync,
+{
+    scan_crates(&collect_uncompressed_paths(src_path)?, filter, scanner);
+    Ok(())
+}
+
+fn scan_crates<Filt, Scan>(paths: &[PathBuf], filter: Filt, scanner: Scan)
+where
+    Filt: Fn(&Path) -> bool + Sync,
+    Scan: Fn(&CrateSources) -> Result<()> + Sync,
+{
+    eprintln!("scanning {} crates", paths.len());
+    let scanned = AtomicU32::new(0);
+    let load_errors = AtomicU32::new(0);
+    let scan_errors = AtomicU32::new(0);
+    paths.par_iter().for_each(|crate_path| {
+        let progress = scanned.fetch_add(1, Ordering::SeqCst);
+        if progress.is_multiple_of(10000) {
+            eprintln!("processed {progress}/{}", paths.len());
+        }
+        match CrateSources::read(crate_path, &filter) {
+            Ok(sources) => {
+                if let Err(e) = scanner(&sources) {
+                    eprintln!("{ERROR} scanning {crate_path:?}: {e:?}");
+                    scan_errors.fetch_add(1, Ordering::SeqCst);
                 }
             }
-        });
+            Err(e) => {
+                load_errors.fetch_add(1, Ordering::SeqCst);
+                eprintln!("load error {crate_path:?}: {e}");
+            }
+        }
+        // See `scan_compressed`.
+        proc_macro2::extra::invalidate_current_thread_spans();
+    });
+    println!(
+        "load errors: {}\n\
+        scan errors: {}\n\
+        total: {}",
+        load_errors.load(Ordering::SeqCst),
+        scan_errors.load(Ordering::SeqCst),
+        paths.len()
+    );
+}
 
-    versions
+fn collect_uncompressed_paths(src_path: &Path) -> Result<Vec<PathBuf>> {
+    let layout = Layout::detect(src_path)?;
+    let (complete, partial): (Vec<_>, Vec<_>) = layout
+        .enumerate(src_path)?
         .into_iter()
-        .map(|(_name, (path, _count))| crates_path.join(path))
-        .collect()
+        .map(|(_id, path)| path)
+        .partition(|path| path.join(extract::MARKER_FILE).exists());
+    if !partial.is_empty() {
+        eprintln!(
+            "warning: skipping {} crates without a completion marker, \
+            run extract-latest again to repair them",
+            partial.len()
+        );
+    }
+    Ok(complete)
+}
+
+pub fn collect_latest_crates(crates_path: &Path) -> Vec<PathBuf> {
+    Selection::from(Versions::Latest).collect(crates_path)
 }
 
 pub fn overdrive(n: usize) {
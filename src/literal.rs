@@ -0,0 +1,319 @@
+//! Decoding literal tokens into their values.
+//!
+//! `proc_macro2::Literal` only has the source text of a literal, with its
+//! prefix, quotes, escapes and suffix. A [`Literal`] keeps that text as
+//! `raw` and adds the kind of literal and its decoded [`Value`], so that
+//! `"a\x62\` + newline + `    c"`, `r#"abc"#` and `c"abc"` all have the
+//! value `abc`. Decoding is syn's, which handles every escape sequence and
+//! string continuation rustc does.
+
+use crate::findings::Span;
+use std::borrow::Cow;
+use std::fmt;
+
+/// A literal token, as written and as decoded.
+#[derive(Clone, Debug)]
+pub struct Literal {
+    pub kind: LiteralKind,
+    /// The source text, like `b"\x00"` or `1_000u32`.
+    pub raw: String,
+    pub value: Value,
+    /// The suffix, like `u32` or `f64`, or empty.
+    pub suffix: String,
+    pub span: Span,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LiteralKind {
+    Str,
+    RawStr,
+    ByteStr,
+    RawByteStr,
+    CStr,
+    RawCStr,
+    Char,
+    Byte,
+    Int,
+    Float,
+}
+
+/// The value of a literal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    /// The value of a string literal.
+    Str(String),
+    /// The value of a byte string, or a C string without its nul
+    /// terminator.
+    Bytes(Vec<u8>),
+    Char(char),
+    Byte(u8),
+    /// The value of an integer in decimal digits, which may not fit in any
+    /// integer type.
+    Int(String),
+    /// The value of a float in decimal digits, like `1e10` or `0.5`.
+    Float(String),
+}
+
+impl Literal {
+    /// Decodes a literal token. Returns `None` for tokens that aren't valid
+    /// literals, which the lexer only produces for error recovery.
+    pub fn new(token: &proc_macro2::Literal) -> Option<Literal> {
+        let raw = token.to_string();
+        let is_raw = |prefix: &str| raw.strip_prefix(prefix).is_some_and(|r| r.starts_with('r'));
+        let (kind, value, suffix) = match syn::Lit::new(token.clone()) {
+            syn::Lit::Str(s) => {
+                let kind = if is_raw("") {
+                    LiteralKind::RawStr
+                } else {
+                    LiteralKind::Str
+                };
+                (kind, Value::Str(s.value()), s.suffix().to_string())
+            }
+            syn::Lit::ByteStr(s) => {
+                let kind = if is_raw("b") {
+                    LiteralKind::RawByteStr
+                } else {
+                    LiteralKind::ByteStr
+                };
+                (kind, Value::Bytes(s.value()), s.suffix().to_string())
+            }
+            syn::Lit::CStr(s) => {
+                let kind = if is_raw("c") {
+                    LiteralKind::RawCStr
+                } else {
+                    LiteralKind::CStr
+                };
+                let bytes = s.value().into_bytes();
+                (kind, Value::Bytes(bytes), s.suffix().to_string())
+            }
+            syn::Lit::Char(c) => (
+                LiteralKind::Char,
+                Value::Char(c.value()),
+                c.suffix().to_string(),
+            ),
+            syn::Lit::Byte(b) => (
+                LiteralKind::Byte,
+                Value::Byte(b.value()),
+                b.suffix().to_string(),
+            ),
+            syn::Lit::Int(i) => (
+                LiteralKind::Int,
+                Value::Int(i.base10_digits().to_string()),
+                i.suffix().to_string(),
+            ),
+            syn::Lit::Float(f) => (
+                LiteralKind::Float,
+                Value::Float(f.base10_digits().to_string()),
+                f.suffix().to_string(),
+            ),
+            _ => return None,
+        };
+        Some(Literal {
+            kind,
+            raw,
+            value,
+            suffix,
+            span: token.span().into(),
+        })
+    }
+
+    /// The value as text, for string, character and byte literals whose
+    /// value is UTF-8. Numbers have none, since their text is just `raw`.
+    pub fn text(&self) -> Option<Cow<'_, str>> {
+        match &self.value {
+            Value::Str(s) => Some(Cow::Borrowed(s)),
+            Value::Bytes(b) => std::str::from_utf8(b).ok().map(Cow::Borrowed),
+            Value::Char(c) => Some(Cow::Owned(c.to_string())),
+            Value::Byte(b) => b.is_ascii().then(|| Cow::Owned(char::from(*b).to_string())),
+            Value::Int(_) | Value::Float(_) => None,
+        }
+    }
+}
+
+impl LiteralKind {
+    /// Whether escapes and string continuations are decoded in this kind of
+    /// literal.
+    pub fn has_escapes(self) -> bool {
+        !matches!(
+            self,
+            LiteralKind::RawStr
+                | LiteralKind::RawByteStr
+                | LiteralKind::RawCStr
+                | LiteralKind::Int
+                | LiteralKind::Float
+        )
+    }
+}
+
+impl fmt::Display for LiteralKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LiteralKind::Str => "str",
+            LiteralKind::RawStr => "raw str",
+            LiteralKind::ByteStr => "byte str",
+            LiteralKind::RawByteStr => "raw byte str",
+            LiteralKind::CStr => "c str",
+            LiteralKind::RawCStr => "raw c str",
+            LiteralKind::Char => "char",
+            LiteralKind::Byte => "byte",
+            LiteralKind::Int => "int",
+            LiteralKind::Float => "float",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proc_macro2::{TokenStream, TokenTree};
+
+    fn lit(src: &str) -> Literal {
+        let tokens: TokenStream = src.parse().unwrap();
+        let Some(TokenTree::Literal(token)) = tokens.into_iter().next() else {
+            panic!("`{src}` is not a literal");
+        };
+        Literal::new(&token).unwrap()
+    }
+
+    fn check(src: &str, kind: LiteralKind, value: Value, suffix: &str) {
+        let lit = lit(src);
+        assert_eq!(lit.kind, kind, "{src}");
+        assert_eq!(lit.value, value, "{src}");
+        assert_eq!(lit.suffix, suffix, "{src}");
+        assert_eq!(lit.raw, src);
+    }
+
+    fn str(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+
+    fn bytes(b: &[u8]) -> Value {
+        Value::Bytes(b.to_vec())
+    }
+
+    #[test]
+    fn strings() {
+        check(r#""abc""#, LiteralKind::Str, str("abc"), "");
+        check(
+            r#""\x41\u{e9}\n\t\\\"""#,
+            LiteralKind::Str,
+            str("Aé\n\t\\\""),
+            "",
+        );
+        check("\"a\\\n    b\"", LiteralKind::Str, str("ab"), "");
+        check("\"a\\\n\n  b\"", LiteralKind::Str, str("ab"), "");
+        check(r#""abc"suffix"#, LiteralKind::Str, str("abc"), "suffix");
+    }
+
+    #[test]
+    fn raw_strings() {
+        check(r#"r"a\nb""#, LiteralKind::RawStr, str(r"a\nb"), "");
+        check(
+            r###"r##"a "# \x41"##"###,
+            LiteralKind::RawStr,
+            str(r##"a "# \x41"##),
+            "",
+        );
+    }
+
+    #[test]
+    fn byte_strings() {
+        check(
+            r#"b"a\x00\xff\n""#,
+            LiteralKind::ByteStr,
+            bytes(b"a\0\xff\n"),
+            "",
+        );
+        check("b\"a\\\n  b\"", LiteralKind::ByteStr, bytes(b"ab"), "");
+        check(
+            r##"br#"\x00"#"##,
+            LiteralKind::RawByteStr,
+            bytes(br"\x00"),
+            "",
+        );
+    }
+
+    #[test]
+    fn c_strings() {
+        check(
+            r#"c"abc\x01\u{e9}""#,
+            LiteralKind::CStr,
+            bytes("abc\x01é".as_bytes()),
+            "",
+        );
+        check(r##"cr#"a\0"#"##, LiteralKind::RawCStr, bytes(br"a\0"), "");
+    }
+
+    #[test]
+    fn chars_and_bytes() {
+        check("'a'", LiteralKind::Char, Value::Char('a'), "");
+        check(r"'\u{1F600}'", LiteralKind::Char, Value::Char('😀'), "");
+        check(r"'\''", LiteralKind::Char, Value::Char('\''), "");
+        check(r"b'\n'", LiteralKind::Byte, Value::Byte(b'\n'), "");
+        check(r"b'\xff'", LiteralKind::Byte, Value::Byte(0xff), "");
+    }
+
+    #[test]
+    fn numbers() {
+        check(
+            "1_000u32",
+            LiteralKind::Int,
+            Value::Int("1000".into()),
+            "u32",
+        );
+        check("0x1F", LiteralKind::Int, Value::Int("31".into()), "");
+        check("0b1010_i8", LiteralKind::Int, Value::Int("10".into()), "i8");
+        check(
+            "1.5e3",
+            LiteralKind::Float,
+            Value::Float("1.5e3".into()),
+            "",
+        );
+        check(
+            "2.5_f64",
+            LiteralKind::Float,
+            Value::Float("2.5".into()),
+            "f64",
+        );
+        // Lexically an integer, even though its type is `f32`.
+        check("1f32", LiteralKind::Int, Value::Int("1".into()), "f32");
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(lit(r#""a\x41""#).text().as_deref(), Some("aA"));
+        assert_eq!(lit(r#"b"\x41""#).text().as_deref(), Some("A"));
+        assert_eq!(lit(r#"b"\xff""#).text(), None);
+        assert_eq!(lit(r#"c"é""#).text().as_deref(), Some("é"));
+        assert_eq!(lit("'é'").text().as_deref(), Some("é"));
+        assert_eq!(lit("b'a'").text().as_deref(), Some("a"));
+        assert_eq!(lit(r"b'\xff'").text(), None);
+        assert_eq!(lit("1").text(), None);
+    }
+
+    #[test]
+    fn escapes_by_kind() {
+        assert!(lit(r#""a""#).kind.has_escapes());
+        assert!(lit(r#"c"a""#).kind.has_escapes());
+        assert!(!lit(r#"r"a""#).kind.has_escapes());
+        assert!(!lit(r#"br"a""#).kind.has_escapes());
+        assert!(!lit("1").kind.has_escapes());
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        // These never get to `Literal::new`, because they don't lex.
+        for src in [
+            r#""\q""#,
+            r#""\x80""#,
+            r#""\u{D800}""#,
+            r#""\u{110000}""#,
+            r#"b"\u{41}""#,
+            r"'\u{110000}'",
+            r#"c"\0""#,
+            "'ab'",
+        ] {
+            assert!(src.parse::<TokenStream>().is_err(), "{src}");
+        }
+    }
+}
@@ -0,0 +1,625 @@
+//! Parsing and comparing `Cargo.lock` files.
+//!
+//! Lockfiles are normalized while parsing so that every format version looks
+//! the same: checksums from the `[metadata]` table of version 1 files are
+//! moved to their packages, and dependencies are always a name and version.
+//! That way a diff only reports the lockfile version when a file was just
+//! rewritten in a newer format.
+
+use crate::CrateId;
+use anyhow::{bail, format_err, Context, Result};
+use semver::Version;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+/// A parsed `Cargo.lock`.
+#[derive(Clone, Debug)]
+pub struct Lockfile {
+    /// The lockfile format version.
+    ///
+    /// Versions 1 and 2 don't record this, so it is guessed from whether
+    /// there is a `[metadata]` table, or dependencies are written out in
+    /// full as `name version (source)` where version 2 would only write the
+    /// name.
+    pub version: u32,
+    /// Packages sorted by name, version and source.
+    pub packages: Vec<Package>,
+}
+
+/// A package in a lockfile.
+#[derive(Clone, Debug)]
+pub struct Package {
+    pub id: CrateId,
+    /// Where the package comes from, or `None` for path dependencies and
+    /// workspace members.
+    pub source: Option<String>,
+    pub checksum: Option<String>,
+    pub dependencies: BTreeSet<CrateId>,
+}
+
+/// A difference between two lockfiles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    LockfileVersion {
+        old: u32,
+        new: u32,
+    },
+    Added(CrateId),
+    Removed(CrateId),
+    /// A package was resolved to a different version.
+    VersionBump {
+        name: String,
+        old: Version,
+        new: Version,
+    },
+    Source {
+        id: CrateId,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    Checksum {
+        id: CrateId,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    DependencyAdded {
+        id: CrateId,
+        dep: CrateId,
+    },
+    DependencyRemoved {
+        id: CrateId,
+        dep: CrateId,
+    },
+}
+
+/// The kind of difference between two lockfiles, ordered from least to most
+/// significant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Class {
+    /// The files are identical.
+    Same,
+    /// The files differ only in ways that don't change their meaning, such
+    /// as ordering or whitespace.
+    Formatting,
+    /// Only the lockfile format version changed.
+    LockfileVersion,
+    /// At most the format version and checksums changed.
+    Checksum,
+    /// A package comes from a different source, but nothing was resolved
+    /// differently.
+    Source,
+    /// Packages were added, removed, bumped, or depend on different
+    /// packages.
+    Resolution,
+}
+
+impl FromStr for Lockfile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Lockfile> {
+        let lock: toml::Value = toml::from_str(s)?;
+        let metadata = lock.get("metadata").and_then(|m| m.as_table());
+        let version = match lock.get("version") {
+            Some(v) => Some(
+                v.as_integer()
+                    .and_then(|v| u32::try_from(v).ok())
+                    .ok_or_else(|| format_err!("invalid lockfile version `{v}`"))?,
+            ),
+            None if metadata.is_some() => Some(1),
+            None => None,
+        };
+
+        struct Raw<'a> {
+            id: CrateId,
+            source: Option<&'a str>,
+            checksum: Option<&'a str>,
+            dependencies: Vec<&'a str>,
+        }
+        let empty = Vec::new();
+        let raw = lock
+            .get("package")
+            .and_then(|p| p.as_array())
+            .unwrap_or(&empty)
+            .iter()
+            .map(|package| {
+                let field = |key| package.get(key).and_then(|v| v.as_str());
+                let name = field("name").context("package is missing `name`")?;
+                let version = field("version")
+                    .with_context(|| format!("package `{name}` is missing `version`"))?;
+                let dependencies = match package.get("dependencies") {
+                    Some(deps) => deps
+                        .as_array()
+                        .and_then(|deps| deps.iter().map(|d| d.as_str()).collect())
+                        .with_context(|| format!("invalid dependencies of `{name}`"))?,
+                    None => Vec::new(),
+                };
+                Ok(Raw {
+                    id: CrateId {
+                        name: name.to_string(),
+                        version: Version::parse(version)
+                            .with_context(|| format!("invalid version of `{name}`"))?,
+                    },
+                    source: field("source"),
+                    checksum: field("checksum"),
+                    dependencies,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Version 2 and later only include the version of a dependency when
+        // more than one version of it is in the lockfile.
+        let mut versions: HashMap<&str, Vec<&Version>> = HashMap::new();
+        for r in &raw {
+            versions.entry(&r.id.name).or_default().push(&r.id.version);
+        }
+        // Version 1 always writes `name version (source)`, even when the
+        // name alone is enough. A version 1 file without checksums has no
+        // `[metadata]`, so that is the only way to tell.
+        let version = version.unwrap_or_else(|| {
+            let spelled_out = raw.iter().flat_map(|r| &r.dependencies).any(|dep| {
+                let mut parts = dep.split(' ');
+                let name = parts.next().unwrap();
+                parts.next().is_some() && versions.get(name).is_some_and(|v| v.len() == 1)
+            });
+            if spelled_out {
+                1
+            } else {
+                2
+            }
+        });
+        let resolve = |dep: &str| -> Result<CrateId> {
+            let mut parts = dep.split(' ');
+            let name = parts.next().unwrap();
+            let version = match parts.next() {
+                Some(v) => Version::parse(v)?,
+                None => match versions.get(name).map(|v| v.as_slice()) {
+                    Some([v]) => (*v).clone(),
+                    _ => bail!("ambiguous or missing dependency `{dep}`"),
+                },
+            };
+            Ok(CrateId {
+                name: name.to_string(),
+                version,
+            })
+        };
+
+        let mut packages = raw
+            .iter()
+            .map(|r| {
+                let checksum = match r.checksum {
+                    Some(c) => Some(c.to_string()),
+                    None => metadata
+                        .and_then(|m| v1_checksum(m, &r.id, r.source))
+                        .map(String::from),
+                };
+                let dependencies = r
+                    .dependencies
+                    .iter()
+                    .map(|d| resolve(d))
+                    .collect::<Result<_>>()
+                    .with_context(|| format!("invalid dependencies of {}", r.id))?;
+                Ok(Package {
+                    id: r.id.clone(),
+                    source: r.source.map(String::from),
+                    checksum,
+                    dependencies,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        packages.sort_by(|a, b| (&a.id, &a.source).cmp(&(&b.id, &b.source)));
+        Ok(Lockfile { version, packages })
+    }
+}
+
+/// Finds the checksum of a package in a version 1 `[metadata]` table, where
+/// the keys look like `checksum NAME VERSION (SOURCE)`.
+fn v1_checksum<'a>(
+    metadata: &'a toml::value::Table,
+    id: &CrateId,
+    source: Option<&str>,
+) -> Option<&'a str> {
+    let key = format!(
+        "checksum {} {} ({})",
+        id.name,
+        id.version,
+        source.unwrap_or_default()
+    );
+    metadata
+        .get(&key)
+        .and_then(|v| v.as_str())
+        .filter(|c| *c != "<none>")
+}
+
+impl Lockfile {
+    /// Returns every difference from `self` to `new`.
+    pub fn diff(&self, new: &Lockfile) -> Vec<Change> {
+        let mut changes = Vec::new();
+        if self.version != new.version {
+            changes.push(Change::LockfileVersion {
+                old: self.version,
+                new: new.version,
+            });
+        }
+        let old_by_name = self.by_name();
+        let new_by_name = new.by_name();
+        let names: BTreeSet<_> = old_by_name.keys().chain(new_by_name.keys()).collect();
+        for name in names {
+            let mut old = old_by_name.get(name).cloned().unwrap_or_default();
+            let mut new = new_by_name.get(name).cloned().unwrap_or_default();
+            // Pair up the packages with this name, preferring exact matches
+            // so that only the leftovers are reported as bumps.
+            let mut pairs = take_pairs(&mut old, &mut new, |a, b| {
+                a.id == b.id && a.source == b.source
+            });
+            pairs.extend(take_pairs(&mut old, &mut new, |a, b| a.id == b.id));
+            if old.len() == 1 && new.len() == 1 {
+                pairs.push((old.pop().unwrap(), new.pop().unwrap()));
+            }
+            for (old, new) in pairs {
+                compare_packages(old, new, &mut changes);
+            }
+            changes.extend(old.iter().map(|p| Change::Removed(p.id.clone())));
+            changes.extend(new.iter().map(|p| Change::Added(p.id.clone())));
+        }
+        changes
+    }
+
+    fn by_name(&self) -> HashMap<&str, Vec<&Package>> {
+        let mut by_name: HashMap<_, Vec<_>> = HashMap::new();
+        for package in &self.packages {
+            by_name
+                .entry(package.id.name.as_str())
+                .or_default()
+                .push(package);
+        }
+        by_name
+    }
+}
+
+/// Removes and returns pairs of packages that match.
+fn take_pairs<'a>(
+    old: &mut Vec<&'a Package>,
+    new: &mut Vec<&'a Package>,
+    matches: impl Fn(&Package, &Package) -> bool,
+) -> Vec<(&'a Package, &'a Package)> {
+    let mut pairs = Vec::new();
+    old.retain(|o| match new.iter().position(|n| matches(o, n)) {
+        Some(i) => {
+            pairs.push((*o, new.remove(i)));
+            false
+        }
+        None => true,
+    });
+    pairs
+}
+
+fn compare_packages(old: &Package, new: &Package, changes: &mut Vec<Change>) {
+    if old.id.version != new.id.version {
+        changes.push(Change::VersionBump {
+            name: new.id.name.clone(),
+            old: old.id.version.clone(),
+            new: new.id.version.clone(),
+        });
+    }
+    if old.source != new.source {
+        changes.push(Change::Source {
+            id: new.id.clone(),
+            old: old.source.clone(),
+            new: new.source.clone(),
+        });
+    }
+    if old.checksum != new.checksum {
+        changes.push(Change::Checksum {
+            id: new.id.clone(),
+            old: old.checksum.clone(),
+            new: new.checksum.clone(),
+        });
+    }
+    for dep in old.dependencies.difference(&new.dependencies) {
+        changes.push(Change::DependencyRemoved {
+            id: new.id.clone(),
+            dep: dep.clone(),
+        });
+    }
+    for dep in new.dependencies.difference(&old.dependencies) {
+        changes.push(Change::DependencyAdded {
+            id: new.id.clone(),
+            dep: dep.clone(),
+        });
+    }
+}
+
+impl Change {
+    pub fn class(&self) -> Class {
+        match self {
+            Change::LockfileVersion { .. } => Class::LockfileVersion,
+            Change::Checksum { .. } => Class::Checksum,
+            Change::Source { .. } => Class::Source,
+            Change::Added(_)
+            | Change::Removed(_)
+            | Change::VersionBump { .. }
+            | Change::DependencyAdded { .. }
+            | Change::DependencyRemoved { .. } => Class::Resolution,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn or_none(s: &Option<String>) -> &str {
+            s.as_deref().unwrap_or("none")
+        }
+        match self {
+            Change::LockfileVersion { old, new } => write!(f, "lockfile version {old} -> {new}"),
+            Change::Added(id) => write!(f, "added {id}"),
+            Change::Removed(id) => write!(f, "removed {id}"),
+            Change::VersionBump { name, old, new } => write!(f, "bumped {name} {old} -> {new}"),
+            Change::Source { id, old, new } => {
+                write!(f, "source of {id}: {} -> {}", or_none(old), or_none(new))
+            }
+            Change::Checksum { id, old, new } => {
+                write!(f, "checksum of {id}: {} -> {}", or_none(old), or_none(new))
+            }
+            Change::DependencyAdded { id, dep } => write!(f, "{id} now depends on {dep}"),
+            Change::DependencyRemoved { id, dep } => write!(f, "{id} no longer depends on {dep}"),
+        }
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Class::Same => "same",
+            Class::Formatting => "formatting only",
+            Class::LockfileVersion => "lockfile version only",
+            Class::Checksum => "checksums",
+            Class::Source => "sources",
+            Class::Resolution => "resolution",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Parses and compares two lockfiles, returning the most significant class
+/// of difference along with every change.
+pub fn compare(old: &str, new: &str) -> Result<(Class, Vec<Change>)> {
+    if old == new {
+        return Ok((Class::Same, Vec::new()));
+    }
+    let old_lock: Lockfile = old.parse().context("failed to parse old lockfile")?;
+    let new_lock: Lockfile = new.parse().context("failed to parse new lockfile")?;
+    let changes = old_lock.diff(&new_lock);
+    let class = changes
+        .iter()
+        .map(|c| c.class())
+        .max()
+        .unwrap_or(Class::Formatting);
+    Ok((class, changes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGISTRY: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+    const V1: &str = r#"
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "itoa 1.0.0 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[[package]]
+name = "itoa"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[metadata]
+"checksum itoa 1.0.0 (registry+https://github.com/rust-lang/crates.io-index)" = "abc"
+"#;
+
+    const V2: &str = r#"
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "itoa",
+]
+
+[[package]]
+name = "itoa"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "abc"
+"#;
+
+    fn id(name: &str, version: &str) -> CrateId {
+        CrateId {
+            name: name.to_string(),
+            version: Version::parse(version).unwrap(),
+        }
+    }
+
+    fn parse(s: &str) -> Lockfile {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn v1() {
+        let lock = parse(V1);
+        assert_eq!(lock.version, 1);
+        let [app, itoa] = &lock.packages[..] else {
+            panic!("expected two packages");
+        };
+        assert_eq!(app.id, id("app", "0.1.0"));
+        assert_eq!(app.source, None);
+        assert_eq!(app.dependencies, BTreeSet::from([id("itoa", "1.0.0")]));
+        assert_eq!(itoa.source.as_deref(), Some(REGISTRY));
+        assert_eq!(itoa.checksum.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn v1_without_checksums() {
+        let without_metadata = V1.split("[metadata]").next().unwrap();
+        let lock = parse(without_metadata);
+        assert_eq!(lock.version, 1);
+        assert_eq!(lock.packages[1].checksum, None);
+    }
+
+    #[test]
+    fn v2() {
+        let lock = parse(V2);
+        assert_eq!(lock.version, 2);
+        assert_eq!(
+            lock.packages[0].dependencies,
+            BTreeSet::from([id("itoa", "1.0.0")])
+        );
+        assert_eq!(lock.packages[1].checksum.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn v2_with_two_versions() {
+        let lock = parse(
+            r#"
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = ["itoa 0.4.8", "itoa 1.0.0"]
+
+[[package]]
+name = "itoa"
+version = "0.4.8"
+
+[[package]]
+name = "itoa"
+version = "1.0.0"
+"#,
+        );
+        assert_eq!(lock.version, 2);
+        assert_eq!(
+            lock.packages[0].dependencies,
+            BTreeSet::from([id("itoa", "0.4.8"), id("itoa", "1.0.0")])
+        );
+    }
+
+    #[test]
+    fn v3() {
+        let lock = parse(&format!("version = 3\n{V2}"));
+        assert_eq!(lock.version, 3);
+        assert_eq!(lock.packages.len(), 2);
+    }
+
+    #[test]
+    fn ambiguous_dependency() {
+        let err = r#"
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = ["itoa"]
+
+[[package]]
+name = "itoa"
+version = "0.4.8"
+
+[[package]]
+name = "itoa"
+version = "1.0.0"
+"#
+        .parse::<Lockfile>()
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("ambiguous or missing dependency `itoa`"));
+    }
+
+    fn classify(old: &str, new: &str) -> (Class, Vec<String>) {
+        let (class, changes) = compare(old, new).unwrap();
+        (class, changes.iter().map(|c| c.to_string()).collect())
+    }
+
+    #[test]
+    fn same_and_formatting() {
+        assert_eq!(classify(V2, V2), (Class::Same, vec![]));
+        let reordered = V2.replace(
+            "checksum = \"abc\"\n",
+            "checksum = \"abc\"\n\n# a comment\n",
+        );
+        assert_eq!(classify(V2, &reordered), (Class::Formatting, vec![]));
+    }
+
+    #[test]
+    fn lockfile_version() {
+        assert_eq!(
+            classify(V1, V2),
+            (
+                Class::LockfileVersion,
+                vec!["lockfile version 1 -> 2".to_string()]
+            )
+        );
+        let v1_without_checksums = V1.split("[metadata]").next().unwrap();
+        let v2_without_checksums = V2.replace("checksum = \"abc\"\n", "");
+        assert_eq!(
+            classify(v1_without_checksums, &v2_without_checksums).0,
+            Class::LockfileVersion
+        );
+        let v3 = format!("version = 3\n{V2}");
+        assert_eq!(classify(V2, &v3).0, Class::LockfileVersion);
+    }
+
+    #[test]
+    fn checksum() {
+        let new = format!("version = 3\n{}", V2.replace("abc", "def"));
+        assert_eq!(
+            classify(V2, &new),
+            (
+                Class::Checksum,
+                vec![
+                    "lockfile version 2 -> 3".to_string(),
+                    "checksum of itoa@1.0.0: abc -> def".to_string(),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn source() {
+        let new = V2.replace(REGISTRY, "sparse+https://index.crates.io/");
+        assert_eq!(
+            classify(V2, &new),
+            (
+                Class::Source,
+                vec![format!(
+                    "source of itoa@1.0.0: {REGISTRY} -> sparse+https://index.crates.io/"
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn resolution() {
+        let bumped = V2.replace("version = \"1.0.0\"", "version = \"1.0.1\"");
+        assert_eq!(
+            classify(V2, &bumped),
+            (
+                Class::Resolution,
+                vec![
+                    "app@0.1.0 no longer depends on itoa@1.0.0".to_string(),
+                    "app@0.1.0 now depends on itoa@1.0.1".to_string(),
+                    "bumped itoa 1.0.0 -> 1.0.1".to_string(),
+                ]
+            )
+        );
+        let added = format!(
+            "{V2}\n[[package]]\nname = \"ryu\"\nversion = \"1.0.0\"\nsource = \"{REGISTRY}\"\n"
+        );
+        assert_eq!(
+            classify(V2, &added),
+            (Class::Resolution, vec!["added ryu@1.0.0".to_string()])
+        );
+        assert_eq!(
+            classify(&added, V2),
+            (Class::Resolution, vec!["removed ryu@1.0.0".to_string()])
+        );
+    }
+}
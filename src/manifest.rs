@@ -0,0 +1,398 @@
+//! Parsing `Cargo.toml` without running cargo.
+//!
+//! [`Manifest`] covers what scanners usually ask about a package: its
+//! targets, dependencies and features. Targets include the ones cargo
+//! discovers automatically (`src/main.rs`, `src/bin/*.rs`, `examples/`,
+//! `tests/`, `benches/`, `build.rs`), which is why parsing needs the list
+//! of files in the crate as well.
+
+use anyhow::{format_err, Context, Result};
+use flate2::read::GzDecoder;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tar::Archive;
+
+/// A parsed `Cargo.toml`.
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    /// The package edition, `2015` if not given.
+    pub edition: String,
+    pub rust_version: Option<String>,
+    pub links: Option<String>,
+    pub targets: Vec<Target>,
+    pub dependencies: Vec<Dependency>,
+    pub features: BTreeMap<String, Vec<String>>,
+    /// The whole manifest.
+    pub raw: toml::Value,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TargetKind {
+    Lib,
+    Bin,
+    Example,
+    Test,
+    Bench,
+    /// The build script.
+    CustomBuild,
+}
+
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub kind: TargetKind,
+    pub name: String,
+    /// The root source file, relative to the package root.
+    pub path: PathBuf,
+    /// The edition if it is set for this target, overriding the package
+    /// edition.
+    pub edition: Option<String>,
+    pub required_features: Vec<String>,
+    /// Whether cargo discovered this target instead of it being listed in
+    /// the manifest.
+    pub inferred: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DepKind {
+    Normal,
+    Dev,
+    Build,
+}
+
+#[derive(Clone, Debug)]
+pub struct Dependency {
+    /// The name the dependency is used as, which is the key in the
+    /// manifest.
+    pub name: String,
+    /// The package name, if the dependency is renamed.
+    pub package: Option<String>,
+    /// The version requirement, `None` for path or git dependencies without
+    /// one.
+    pub req: Option<String>,
+    pub kind: DepKind,
+    /// The `cfg` or target triple for platform-specific dependencies.
+    pub target: Option<String>,
+    pub optional: bool,
+    pub default_features: bool,
+    pub features: Vec<String>,
+}
+
+impl Manifest {
+    /// Parses a manifest.
+    ///
+    /// `files` is every file in the package, relative to its root, and is
+    /// used to discover targets.
+    pub fn parse(contents: &str, files: &[PathBuf]) -> Result<Manifest> {
+        let raw: toml::Value = toml::from_str(contents)?;
+        let package = raw
+            .get("package")
+            .or_else(|| raw.get("project"))
+            .ok_or_else(|| format_err!("manifest has no `[package]`"))?;
+        let string = |v: &toml::Value, key| v.get(key).and_then(|v| v.as_str()).map(String::from);
+        let name = string(package, "name").context("package has no name")?;
+        let edition = string(package, "edition").unwrap_or_else(|| "2015".to_string());
+        let targets = Discovery {
+            raw: &raw,
+            package,
+            name: &name,
+            edition: &edition,
+            files,
+        }
+        .targets();
+        let features = raw
+            .get("features")
+            .and_then(|f| f.as_table())
+            .map(|table| {
+                table
+                    .iter()
+                    .map(|(name, values)| (name.clone(), string_array(Some(values))))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(Manifest {
+            version: string(package, "version").unwrap_or_default(),
+            rust_version: string(package, "rust-version"),
+            links: string(package, "links"),
+            dependencies: dependencies(&raw),
+            name,
+            edition,
+            targets,
+            features,
+            raw,
+        })
+    }
+
+    /// Reads the manifest of a `.crate` file.
+    pub fn from_crate_file(crate_path: &Path) -> Result<Manifest> {
+        let f = GzDecoder::new(File::open(crate_path)?);
+        let mut archive = Archive::new(f);
+        let mut files = Vec::new();
+        let mut contents = None;
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?;
+            // Strip the `name-version/` directory.
+            let path: PathBuf = path.components().skip(1).collect();
+            if path == Path::new("Cargo.toml") {
+                let mut s = String::new();
+                entry.read_to_string(&mut s)?;
+                contents = Some(s);
+            }
+            files.push(path);
+        }
+        let contents = contents.ok_or_else(|| format_err!("no Cargo.toml in {crate_path:?}"))?;
+        Manifest::parse(&contents, &files)
+    }
+
+    /// Reads the manifest of an extracted crate.
+    pub fn from_dir(crate_dir: &Path) -> Result<Manifest> {
+        let contents = std::fs::read_to_string(crate_dir.join("Cargo.toml"))?;
+        let files: Vec<_> = walkdir::WalkDir::new(crate_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.path().strip_prefix(crate_dir).unwrap().to_path_buf())
+            .collect();
+        Manifest::parse(&contents, &files)
+    }
+
+    /// Returns the edition a target is compiled with.
+    pub fn target_edition<'a>(&'a self, target: &'a Target) -> &'a str {
+        target.edition.as_deref().unwrap_or(&self.edition)
+    }
+}
+
+/// Finds the targets of a package the way cargo does.
+struct Discovery<'a> {
+    raw: &'a toml::Value,
+    package: &'a toml::Value,
+    name: &'a str,
+    edition: &'a str,
+    files: &'a [PathBuf],
+}
+
+impl Discovery<'_> {
+    fn targets(&self) -> Vec<Target> {
+        let mut targets = Vec::new();
+        let lib_name = self.name.replace('-', "_");
+        match self.raw.get("lib") {
+            Some(lib) => targets.push(self.explicit(TargetKind::Lib, lib, &lib_name, "src/lib.rs")),
+            None if self.auto("autolib", false) && self.has("src/lib.rs") => {
+                targets.push(inferred(TargetKind::Lib, &lib_name, "src/lib.rs"))
+            }
+            None => {}
+        }
+        for (kind, key, auto, dir) in [
+            (TargetKind::Bin, "bin", "autobins", "src/bin"),
+            (TargetKind::Example, "example", "autoexamples", "examples"),
+            (TargetKind::Test, "test", "autotests", "tests"),
+            (TargetKind::Bench, "bench", "autobenches", "benches"),
+        ] {
+            let explicit: Vec<_> = self
+                .raw
+                .get(key)
+                .and_then(|t| t.as_array())
+                .map(|a| a.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|t| {
+                    let name = t.get("name")?.as_str()?;
+                    let default = self.default_path(kind, dir, name);
+                    Some(self.explicit(kind, t, name, &default))
+                })
+                .collect();
+            if self.auto(auto, !explicit.is_empty()) {
+                let mut found = self.discover(dir);
+                if kind == TargetKind::Bin && self.has("src/main.rs") {
+                    found.insert(0, (self.name.to_string(), PathBuf::from("src/main.rs")));
+                }
+                for (name, path) in found {
+                    if !explicit.iter().any(|t| t.name == name || t.path == path) {
+                        targets.push(inferred(kind, &name, path));
+                    }
+                }
+            }
+            targets.extend(explicit);
+        }
+        let build = match self.package.get("build") {
+            Some(toml::Value::String(path)) => Some(PathBuf::from(path)),
+            Some(toml::Value::Boolean(false)) => None,
+            _ => self.has("build.rs").then(|| PathBuf::from("build.rs")),
+        };
+        if let Some(path) = build {
+            targets.push(Target {
+                kind: TargetKind::CustomBuild,
+                name: "build-script-build".to_string(),
+                path,
+                edition: None,
+                required_features: Vec::new(),
+                inferred: self.package.get("build").is_none(),
+            });
+        }
+        targets
+    }
+
+    fn has(&self, path: &str) -> bool {
+        self.files.iter().any(|f| f == Path::new(path))
+    }
+
+    /// Whether a kind of target is discovered automatically.
+    ///
+    /// The 2015 edition turns discovery off if any target of that kind is
+    /// listed explicitly.
+    fn auto(&self, key: &str, has_explicit: bool) -> bool {
+        match self.package.get(key).and_then(|v| v.as_bool()) {
+            Some(auto) => auto,
+            None => !(self.edition == "2015" && has_explicit),
+        }
+    }
+
+    /// Finds `dir/*.rs` and `dir/*/main.rs`.
+    fn discover(&self, dir: &str) -> Vec<(String, PathBuf)> {
+        let mut found: Vec<_> = self
+            .files
+            .iter()
+            .filter_map(|f| {
+                let rest = f.strip_prefix(dir).ok()?;
+                let mut parts = rest.iter();
+                let first = Path::new(parts.next()?);
+                match (parts.next(), parts.next()) {
+                    (None, _) if first.extension()? == "rs" => {
+                        Some((first.file_stem()?.to_str()?.to_string(), f.clone()))
+                    }
+                    (Some(main), None) if main == "main.rs" => {
+                        Some((first.to_str()?.to_string(), f.clone()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        found.sort();
+        found
+    }
+
+    fn default_path(&self, kind: TargetKind, dir: &str, name: &str) -> String {
+        if kind == TargetKind::Bin && name == self.name && self.has("src/main.rs") {
+            return "src/main.rs".to_string();
+        }
+        let nested = format!("{dir}/{name}/main.rs");
+        if self.has(&nested) {
+            nested
+        } else {
+            format!("{dir}/{name}.rs")
+        }
+    }
+
+    fn explicit(&self, kind: TargetKind, t: &toml::Value, name: &str, default: &str) -> Target {
+        let string = |key| t.get(key).and_then(|v| v.as_str()).map(String::from);
+        Target {
+            kind,
+            name: string("name").unwrap_or_else(|| name.to_string()),
+            path: PathBuf::from(string("path").unwrap_or_else(|| default.to_string())),
+            edition: string("edition"),
+            required_features: string_array(t.get("required-features")),
+            inferred: false,
+        }
+    }
+}
+
+fn inferred(kind: TargetKind, name: &str, path: impl Into<PathBuf>) -> Target {
+    Target {
+        kind,
+        name: name.to_string(),
+        path: path.into(),
+        edition: None,
+        required_features: Vec::new(),
+        inferred: true,
+    }
+}
+
+fn string_array(v: Option<&toml::Value>) -> Vec<String> {
+    v.and_then(|v| v.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|s| s.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Collects the dependencies from every dependency table, including the
+/// platform-specific ones.
+fn dependencies(raw: &toml::Value) -> Vec<Dependency> {
+    let mut deps = Vec::new();
+    let mut add_tables = |table: &toml::Value, target: Option<&str>| {
+        for (keys, kind) in [
+            (&["dependencies"][..], DepKind::Normal),
+            (&["dev-dependencies", "dev_dependencies"], DepKind::Dev),
+            (
+                &["build-dependencies", "build_dependencies"],
+                DepKind::Build,
+            ),
+        ] {
+            for key in keys {
+                let Some(list) = table.get(key).and_then(|d| d.as_table()) else {
+                    continue;
+                };
+                for (name, dep) in list {
+                    deps.push(dependency(name, dep, kind, target));
+                }
+            }
+        }
+    };
+    add_tables(raw, None);
+    if let Some(targets) = raw.get("target").and_then(|t| t.as_table()) {
+        for (target, table) in targets {
+            add_tables(table, Some(target));
+        }
+    }
+    deps
+}
+
+fn dependency(name: &str, dep: &toml::Value, kind: DepKind, target: Option<&str>) -> Dependency {
+    let string = |key| dep.get(key).and_then(|v| v.as_str()).map(String::from);
+    let flag = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|k| dep.get(k).and_then(|v| v.as_bool()))
+    };
+    Dependency {
+        name: name.to_string(),
+        package: string("package"),
+        req: dep.as_str().map(String::from).or_else(|| string("version")),
+        kind,
+        target: target.map(String::from),
+        optional: flag(&["optional"]).unwrap_or(false),
+        default_features: flag(&["default-features", "default_features"]).unwrap_or(true),
+        features: string_array(dep.get("features")),
+    }
+}
+
+impl fmt::Display for TargetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TargetKind::Lib => "lib",
+            TargetKind::Bin => "bin",
+            TargetKind::Example => "example",
+            TargetKind::Test => "test",
+            TargetKind::Bench => "bench",
+            TargetKind::CustomBuild => "custom-build",
+        };
+        f.write_str(s)
+    }
+}
+
+impl fmt::Display for DepKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DepKind::Normal => "normal",
+            DepKind::Dev => "dev",
+            DepKind::Build => "build",
+        };
+        f.write_str(s)
+    }
+}
@@ -0,0 +1,455 @@
+//! Finding which target each `.rs` file of a crate is compiled into.
+//!
+//! [`ModuleGraph`] starts at the root of every target in the [`Manifest`]
+//! and follows `mod` declarations the way rustc does, including `#[path]`
+//! attributes and inline modules. Files that no target reaches are test
+//! fixtures, `include!`d fragments, generated or dead code, and are
+//! [`FileKind::Unreachable`].
+//!
+//! Declarations are found in tokens rather than a syntax tree, so files syn
+//! can't parse are still followed, and so are modules declared inside macro
+//! invocations like `cfg_if!`. Every `cfg` is assumed to be enabled, and
+//! every path a `cfg_attr(..., path = ...)` could give is followed.
+
+use crate::manifest::{Manifest, Target, TargetKind};
+use crate::CrateSources;
+use anyhow::{bail, format_err, Result};
+use proc_macro2::{Delimiter, TokenStream, TokenTree};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+/// What a file is compiled as. A file in several targets has the kind that
+/// comes first here.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FileKind {
+    Lib,
+    Bin,
+    BuildScript,
+    Example,
+    Test,
+    Bench,
+    Unreachable,
+}
+
+impl From<TargetKind> for FileKind {
+    fn from(kind: TargetKind) -> FileKind {
+        match kind {
+            TargetKind::Lib => FileKind::Lib,
+            TargetKind::Bin => FileKind::Bin,
+            TargetKind::Example => FileKind::Example,
+            TargetKind::Test => FileKind::Test,
+            TargetKind::Bench => FileKind::Bench,
+            TargetKind::CustomBuild => FileKind::BuildScript,
+        }
+    }
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FileKind::Lib => "lib",
+            FileKind::Bin => "bin",
+            FileKind::BuildScript => "build",
+            FileKind::Example => "example",
+            FileKind::Test => "test",
+            FileKind::Bench => "bench",
+            FileKind::Unreachable => "unreachable",
+        })
+    }
+}
+
+impl FromStr for FileKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<FileKind> {
+        Ok(match s {
+            "lib" => FileKind::Lib,
+            "bin" => FileKind::Bin,
+            "build" => FileKind::BuildScript,
+            "example" => FileKind::Example,
+            "test" => FileKind::Test,
+            "bench" => FileKind::Bench,
+            "unreachable" => FileKind::Unreachable,
+            _ => bail!(
+                "unknown file kind `{s}`, expected \
+                `lib`, `bin`, `build`, `example`, `test`, `bench` or `unreachable`"
+            ),
+        })
+    }
+}
+
+/// Parses a comma-separated list of file kinds, like `lib,bin`.
+pub fn parse_kinds(s: &str) -> Result<HashSet<FileKind>> {
+    s.split(',').map(|kind| kind.trim().parse()).collect()
+}
+
+/// The `.rs` files reachable from each target of a package.
+#[derive(Clone, Debug, Default)]
+pub struct ModuleGraph {
+    /// Every reachable file, relative to the package root, with the targets
+    /// it's compiled into, in [`FileKind`] order.
+    files: BTreeMap<PathBuf, Vec<Target>>,
+}
+
+impl ModuleGraph {
+    /// Follows the modules of every target in `manifest`. `sources` has the
+    /// contents of the package's `.rs` files, relative to its root.
+    pub fn build(manifest: &Manifest, sources: &BTreeMap<PathBuf, &str>) -> ModuleGraph {
+        let mut files: BTreeMap<PathBuf, Vec<Target>> = BTreeMap::new();
+        for target in &manifest.targets {
+            let mut seen = HashSet::new();
+            // Target roots own their directory, like `mod.rs` files.
+            let mut stack = vec![(target.path.clone(), true)];
+            while let Some((path, owns_dir)) = stack.pop() {
+                let Some(contents) = sources.get(&path) else {
+                    continue;
+                };
+                if !seen.insert(path.clone()) {
+                    continue;
+                }
+                let file_dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+                let mod_dir = if owns_dir {
+                    file_dir.clone()
+                } else {
+                    path.with_extension("")
+                };
+                if let Ok(tokens) = contents.parse::<TokenStream>() {
+                    let mut decls = Vec::new();
+                    declarations(tokens, &file_dir, &mod_dir, false, &mut decls);
+                    for decl in decls {
+                        // Files given by `#[path]` own their directory.
+                        stack.extend(decl.attr_paths.into_iter().map(|p| (p, true)));
+                        if let Some(found) = decl
+                            .default_paths
+                            .into_iter()
+                            .find(|p| sources.contains_key(p))
+                        {
+                            let owns_dir = found.file_name().is_some_and(|n| n == "mod.rs");
+                            stack.push((found, owns_dir));
+                        }
+                    }
+                }
+                files.entry(path).or_default().push(target.clone());
+            }
+        }
+        for targets in files.values_mut() {
+            targets.sort_by_key(|t| FileKind::from(t.kind));
+        }
+        ModuleGraph { files }
+    }
+
+    /// The targets `path` (relative to the package root) is compiled into,
+    /// empty if it's unreachable.
+    pub fn targets(&self, path: &Path) -> &[Target] {
+        self.files.get(path).map_or(&[], |t| t.as_slice())
+    }
+
+    /// What `path` (relative to the package root) is compiled as.
+    pub fn kind(&self, path: &Path) -> FileKind {
+        self.targets(path)
+            .first()
+            .map_or(FileKind::Unreachable, |t| t.kind.into())
+    }
+}
+
+/// A `mod name;` declaration and the files it may refer to.
+struct ModDecl {
+    /// The paths given by `#[path]` and `#[cfg_attr(..., path)]`, which are
+    /// all followed.
+    attr_paths: Vec<PathBuf>,
+    /// `name.rs` and `name/mod.rs`, the first of which that exists is
+    /// followed. Empty if there is an unconditional `#[path]`.
+    default_paths: Vec<PathBuf>,
+}
+
+/// Collects the out-of-line module declarations in `tokens`.
+///
+/// `file_dir` is the directory of the file, which `#[path]` is relative to
+/// outside of inline modules, and `mod_dir` is where its submodules are.
+fn declarations(
+    tokens: TokenStream,
+    file_dir: &Path,
+    mod_dir: &Path,
+    inline: bool,
+    decls: &mut Vec<ModDecl>,
+) {
+    let tokens: Vec<_> = tokens.into_iter().collect();
+    let mut paths = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            TokenTree::Punct(p) if p.as_char() == '#' => {
+                if let Some(TokenTree::Group(g)) = tokens.get(i + 1) {
+                    if g.delimiter() == Delimiter::Bracket {
+                        paths.extend(path_attr(g.stream()));
+                        i += 2;
+                        continue;
+                    }
+                }
+            }
+            TokenTree::Ident(ident) if ident == "mod" => {
+                if let Some(TokenTree::Ident(name)) = tokens.get(i + 1) {
+                    let name = name.to_string();
+                    let name = name.strip_prefix("r#").unwrap_or(&name);
+                    match tokens.get(i + 2) {
+                        Some(TokenTree::Punct(p)) if p.as_char() == ';' => {
+                            let attr_dir = if inline { mod_dir } else { file_dir };
+                            let default_paths = if paths.iter().any(|(_, cfg)| !cfg) {
+                                Vec::new()
+                            } else {
+                                vec![
+                                    mod_dir.join(format!("{name}.rs")),
+                                    mod_dir.join(name).join("mod.rs"),
+                                ]
+                            };
+                            decls.push(ModDecl {
+                                attr_paths: paths
+                                    .drain(..)
+                                    .map(|(p, _)| normalize(&attr_dir.join(p)))
+                                    .collect(),
+                                default_paths,
+                            });
+                            i += 3;
+                            continue;
+                        }
+                        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => {
+                            // `#[path]` on an inline module sets its directory.
+                            let dir = match paths.drain(..).next_back() {
+                                Some((path, _)) if !inline => normalize(&file_dir.join(path)),
+                                Some((path, _)) => normalize(&mod_dir.join(path)),
+                                None => mod_dir.join(name),
+                            };
+                            declarations(g.stream(), file_dir, &dir, true, decls);
+                            i += 3;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            // Macro invocations like `cfg_if! { ... }` and blocks can
+            // declare modules too.
+            TokenTree::Group(g) => {
+                declarations(g.stream(), file_dir, mod_dir, inline, decls);
+                if g.delimiter() == Delimiter::Brace {
+                    paths.clear();
+                }
+            }
+            TokenTree::Punct(p) if p.as_char() == ';' => paths.clear(),
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+/// Returns the path in a `path = "..."` or `cfg_attr(..., path = "...")`
+/// attribute, and whether it's the latter.
+fn path_attr(attr: TokenStream) -> Option<(String, bool)> {
+    let tokens: Vec<_> = attr.into_iter().collect();
+    match tokens.as_slice() {
+        [TokenTree::Ident(ident), TokenTree::Punct(eq), TokenTree::Literal(lit)]
+            if ident == "path" && eq.as_char() == '=' =>
+        {
+            syn::parse_str::<syn::LitStr>(&lit.to_string())
+                .ok()
+                .map(|s| (s.value(), false))
+        }
+        [TokenTree::Ident(ident), TokenTree::Group(args)] if ident == "cfg_attr" => {
+            let args: Vec<_> = args.stream().into_iter().collect();
+            let start = args.len().checked_sub(3)?;
+            let (path, _) = path_attr(args[start..].iter().cloned().collect())?;
+            Some((path, true))
+        }
+        _ => None,
+    }
+}
+
+/// Removes `.` and `..` components, like `src/../other/x.rs` to
+/// `other/x.rs`, without looking at the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// Whether a file is needed for [`CrateFiles`], which is the package's
+/// `Cargo.toml` and every `.rs` file. `path` starts with the crate's
+/// directory, as passed to scanners.
+pub fn is_package_file(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "rs") || package_path(path) == Path::new("Cargo.toml")
+}
+
+/// The manifest and module graph of a crate.
+pub struct CrateFiles {
+    /// The crate, like `serde@1.0.0`.
+    pub krate: String,
+    /// `None` if the manifest couldn't be read.
+    pub manifest: Option<Manifest>,
+    pub graph: ModuleGraph,
+}
+
+impl CrateFiles {
+    /// Finds what the files of a crate are compiled as. `krate` must have
+    /// the sources of the files accepted by [`is_package_file`]. A warning
+    /// is printed if its manifest can't be read.
+    pub fn new(krate: &CrateSources) -> CrateFiles {
+        let name = crate::crate_name(&krate.path);
+        match read_package(krate) {
+            Ok((manifest, sources)) => {
+                let graph = ModuleGraph::build(&manifest, &sources);
+                CrateFiles {
+                    krate: name,
+                    manifest: Some(manifest),
+                    graph,
+                }
+            }
+            Err(e) => {
+                eprintln!("warning: failed to read the manifest of {name}: {e}");
+                CrateFiles {
+                    krate: name,
+                    manifest: None,
+                    graph: ModuleGraph::default(),
+                }
+            }
+        }
+    }
+
+    /// What a file is compiled as, or `None` if the manifest couldn't be
+    /// read. `entry_path` starts with the crate's directory, as passed to
+    /// scanners.
+    pub fn kind(&self, entry_path: &Path) -> Option<FileKind> {
+        self.manifest.as_ref()?;
+        Some(self.graph.kind(&package_path(entry_path)))
+    }
+
+    /// The edition a file is compiled with, which is the package edition
+    /// for unreachable files, or `None` if the manifest couldn't be read.
+    pub fn edition(&self, entry_path: &Path) -> Option<&str> {
+        let manifest = self.manifest.as_ref()?;
+        let path = package_path(entry_path);
+        Some(match self.graph.targets(&path).first() {
+            Some(target) => manifest.target_edition(target),
+            None => &manifest.edition,
+        })
+    }
+}
+
+/// Strips the `name-version/` directory from an entry path.
+fn package_path(entry_path: &Path) -> PathBuf {
+    entry_path.components().skip(1).collect()
+}
+
+/// Parses the manifest of a crate, and returns it with the crate's `.rs`
+/// files, relative to the package root.
+fn read_package(krate: &CrateSources) -> Result<(Manifest, BTreeMap<PathBuf, &str>)> {
+    let mut manifest = None;
+    let mut sources = BTreeMap::new();
+    for (path, contents) in &krate.sources {
+        let path = package_path(path);
+        if path == Path::new("Cargo.toml") {
+            manifest = Some(contents);
+        } else if path.extension().is_some_and(|e| e == "rs") {
+            sources.insert(path, contents.as_str());
+        }
+    }
+    let manifest = manifest.ok_or_else(|| format_err!("no Cargo.toml in {:?}", krate.path))?;
+    let files: Vec<_> = krate.files.iter().map(|p| package_path(p)).collect();
+    Ok((Manifest::parse(manifest, &files)?, sources))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A crate read from `files`, which are relative to its directory.
+    fn crate_sources(files: &[(&str, &str)]) -> CrateSources {
+        let files: Vec<_> = files
+            .iter()
+            .map(|(path, contents)| (Path::new("c-1.0.0").join(path), contents.to_string()))
+            .collect();
+        CrateSources {
+            path: PathBuf::from("c-1.0.0"),
+            files: files.iter().map(|(path, _)| path.clone()).collect(),
+            sources: files
+                .into_iter()
+                .filter(|(path, _)| is_package_file(path))
+                .collect(),
+        }
+    }
+
+    fn kind(files: &CrateFiles, path: &str) -> Option<FileKind> {
+        files.kind(&Path::new("c-1.0.0").join(path))
+    }
+
+    const MANIFEST: &str = "[package]\nname = \"c\"\nversion = \"1.0.0\"\nedition = \"2021\"\n";
+
+    #[test]
+    fn package_files() {
+        assert!(is_package_file(Path::new("c-1.0.0/Cargo.toml")));
+        assert!(is_package_file(Path::new("c-1.0.0/src/lib.rs")));
+        assert!(!is_package_file(Path::new("c-1.0.0/sub/Cargo.toml")));
+        assert!(!is_package_file(Path::new("c-1.0.0/README.md")));
+    }
+
+    #[test]
+    fn kinds() {
+        let krate = crate_sources(&[
+            ("Cargo.toml", MANIFEST),
+            ("build.rs", "fn main() {}"),
+            (
+                "src/lib.rs",
+                "mod a; cfg_if! { mod b; } #[path = \"x/c.rs\"] mod c;",
+            ),
+            ("src/a.rs", "mod inner;"),
+            ("src/a/inner.rs", ""),
+            ("src/b/mod.rs", ""),
+            ("src/x/c.rs", ""),
+            ("src/main.rs", "mod a;"),
+            ("tests/t.rs", ""),
+            ("tests/fixtures/f.rs", ""),
+        ]);
+        let files = CrateFiles::new(&krate);
+        assert_eq!(files.krate, "c@1.0.0");
+        assert_eq!(kind(&files, "src/lib.rs"), Some(FileKind::Lib));
+        assert_eq!(kind(&files, "src/a/inner.rs"), Some(FileKind::Lib));
+        assert_eq!(kind(&files, "src/b/mod.rs"), Some(FileKind::Lib));
+        assert_eq!(kind(&files, "src/x/c.rs"), Some(FileKind::Lib));
+        assert_eq!(kind(&files, "src/main.rs"), Some(FileKind::Bin));
+        assert_eq!(kind(&files, "build.rs"), Some(FileKind::BuildScript));
+        assert_eq!(kind(&files, "tests/t.rs"), Some(FileKind::Test));
+        assert_eq!(
+            kind(&files, "tests/fixtures/f.rs"),
+            Some(FileKind::Unreachable)
+        );
+        assert_eq!(files.edition(Path::new("c-1.0.0/src/lib.rs")), Some("2021"));
+    }
+
+    #[test]
+    fn without_manifest() {
+        let files = CrateFiles::new(&crate_sources(&[("src/lib.rs", "")]));
+        assert!(files.manifest.is_none());
+        assert_eq!(kind(&files, "src/lib.rs"), None);
+    }
+
+    #[test]
+    fn loading_keeps_spans() {
+        // Loading tokenizes every file of the crate, which must not
+        // invalidate the spans of tokens made before it.
+        let tokens: TokenStream = "\n  first".parse().unwrap();
+        let span = tokens.into_iter().next().unwrap().span();
+        let krate = crate_sources(&[("Cargo.toml", MANIFEST), ("src/lib.rs", "mod a;")]);
+        CrateFiles::new(&krate);
+        assert_eq!((span.start().line, span.start().column), (2, 2));
+        assert_eq!(span.source_text().as_deref(), Some("first"));
+    }
+}
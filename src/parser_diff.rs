@@ -0,0 +1,801 @@
+//! Differential testing of parsers.
+//!
+//! Every [`ParserUnderTest`] parses the same input into a
+//! [`NormalizedValue`], and a [`Harness`] reports where they disagree:
+//! one parser accepting what another rejects, or two parsers producing
+//! different values. Parse errors are bucketed by message so the summary
+//! shows which kinds of errors are common. [`Harness::report`] shrinks each
+//! disagreeing input to a minimal reproducer with [`crate::reduce`].
+//!
+//! Parsers built on serde can deserialize straight into a
+//! [`NormalizedValue`], so comparing a new parser version takes a few lines:
+//!
+//! ```ignore
+//! let harness = Harness::new()
+//!     .parser(from_fn("toml 0.5", |s| Ok(toml5::from_str(s)?)))
+//!     .parser(from_fn("toml 0.7", |s| Ok(toml7::from_str(s)?)));
+//! ```
+
+use crate::reduce::{self, Corpus};
+use crate::results::Tally;
+use anyhow::Result;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The key the `toml` crates use to pass datetimes through serde.
+const TOML_DATETIME_FIELD: &str = "$__toml_private_datetime";
+
+/// A parsed document in a form that can be compared between parsers and
+/// formats.
+#[derive(Clone, Debug)]
+pub enum NormalizedValue {
+    Null,
+    Boolean(bool),
+    Integer(i128),
+    Float(f64),
+    String(String),
+    /// A datetime, as the parser displays it.
+    Datetime(String),
+    Array(Vec<NormalizedValue>),
+    Table(BTreeMap<String, NormalizedValue>),
+}
+
+impl NormalizedValue {
+    /// The name of the type, for reports.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            NormalizedValue::Null => "null",
+            NormalizedValue::Boolean(_) => "boolean",
+            NormalizedValue::Integer(_) => "integer",
+            NormalizedValue::Float(_) => "float",
+            NormalizedValue::String(_) => "string",
+            NormalizedValue::Datetime(_) => "datetime",
+            NormalizedValue::Array(_) => "array",
+            NormalizedValue::Table(_) => "table",
+        }
+    }
+}
+
+impl PartialEq for NormalizedValue {
+    /// Floats are equal if they have the same bits, so `nan` equals `nan`
+    /// but `-0.0` does not equal `0.0`.
+    fn eq(&self, other: &NormalizedValue) -> bool {
+        use NormalizedValue::*;
+        match (self, other) {
+            (Null, Null) => true,
+            (Boolean(a), Boolean(b)) => a == b,
+            (Integer(a), Integer(b)) => a == b,
+            (Float(a), Float(b)) => a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan()),
+            (String(a), String(b)) => a == b,
+            (Datetime(a), Datetime(b)) => a == b,
+            (Array(a), Array(b)) => a == b,
+            (Table(a), Table(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for NormalizedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NormalizedValue::Null => write!(f, "null"),
+            NormalizedValue::Boolean(b) => write!(f, "{b}"),
+            NormalizedValue::Integer(i) => write!(f, "{i}"),
+            NormalizedValue::Float(x) => write!(f, "{x:?}"),
+            NormalizedValue::String(s) => write!(f, "{s:?}"),
+            NormalizedValue::Datetime(d) => write!(f, "{d}"),
+            NormalizedValue::Array(a) => {
+                write!(f, "[")?;
+                for (i, v) in a.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{v}")?;
+                }
+                write!(f, "]")
+            }
+            NormalizedValue::Table(t) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in t.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " {} = {v}", key(k))?;
+                }
+                write!(f, " }}")
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for NormalizedValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<NormalizedValue, D::Error> {
+        deserializer.deserialize_any(NormalizedVisitor)
+    }
+}
+
+struct NormalizedVisitor;
+
+impl<'de> Visitor<'de> for NormalizedVisitor {
+    type Value = NormalizedValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<NormalizedValue, E> {
+        Ok(NormalizedValue::Boolean(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<NormalizedValue, E> {
+        Ok(NormalizedValue::Integer(v.into()))
+    }
+
+    fn visit_i128<E>(self, v: i128) -> Result<NormalizedValue, E> {
+        Ok(NormalizedValue::Integer(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<NormalizedValue, E> {
+        Ok(NormalizedValue::Integer(v.into()))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<NormalizedValue, E> {
+        Ok(NormalizedValue::Float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<NormalizedValue, E> {
+        Ok(NormalizedValue::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<NormalizedValue, E> {
+        Ok(NormalizedValue::String(v))
+    }
+
+    fn visit_unit<E>(self) -> Result<NormalizedValue, E> {
+        Ok(NormalizedValue::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<NormalizedValue, E> {
+        Ok(NormalizedValue::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<NormalizedValue, D::Error> {
+        NormalizedValue::deserialize(d)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<NormalizedValue, A::Error> {
+        let mut values = Vec::new();
+        while let Some(v) = seq.next_element()? {
+            values.push(v);
+        }
+        Ok(NormalizedValue::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<NormalizedValue, A::Error> {
+        let mut table = BTreeMap::new();
+        while let Some(k) = map.next_key::<String>()? {
+            let v: NormalizedValue = map.next_value()?;
+            if k == TOML_DATETIME_FIELD {
+                let NormalizedValue::String(d) = v else {
+                    return Err(de::Error::custom("expected a datetime string"));
+                };
+                return Ok(NormalizedValue::Datetime(d));
+            }
+            if table.insert(k.clone(), v).is_some() {
+                return Err(de::Error::custom(format!("duplicate key `{k}`")));
+            }
+        }
+        Ok(NormalizedValue::Table(table))
+    }
+}
+
+/// A parser to compare against others.
+pub trait ParserUnderTest: Sync {
+    /// A short name for reports, like `toml 0.7`.
+    fn name(&self) -> &str;
+
+    /// Parses a whole document.
+    fn parse(&self, contents: &str) -> Result<NormalizedValue>;
+
+    /// The bucket a parse error is counted in.
+    fn error_bucket(&self, error: &anyhow::Error) -> String {
+        error_bucket(&error.to_string())
+    }
+}
+
+/// A [`ParserUnderTest`] made from a name and a function.
+pub struct FnParser<F> {
+    name: String,
+    parse: F,
+}
+
+/// Makes a [`ParserUnderTest`] from a function.
+pub fn from_fn<F>(name: &str, parse: F) -> FnParser<F>
+where
+    F: Fn(&str) -> Result<NormalizedValue> + Sync,
+{
+    FnParser {
+        name: name.to_string(),
+        parse,
+    }
+}
+
+impl<F> ParserUnderTest for FnParser<F>
+where
+    F: Fn(&str) -> Result<NormalizedValue> + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn parse(&self, contents: &str) -> Result<NormalizedValue> {
+        (self.parse)(contents)
+    }
+}
+
+/// Reduces an error message to the parts that don't depend on the input's
+/// layout, so the same kind of error ends up in the same bucket.
+///
+/// This drops source excerpts (lines with a `|` gutter) and the `TOML parse
+/// error at line N` header, and replaces numbers with `N`.
+pub fn error_bucket(message: &str) -> String {
+    let lines: Vec<_> = message
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            !line.is_empty()
+                && !line.starts_with("TOML parse error")
+                && !line
+                    .trim_start_matches(|c: char| c.is_ascii_digit() || c == ' ')
+                    .starts_with('|')
+        })
+        .collect();
+    let mut bucket = String::new();
+    let mut in_number = false;
+    for c in lines.join("; ").chars() {
+        if c.is_ascii_digit() {
+            if !in_number {
+                bucket.push('N');
+            }
+            in_number = true;
+        } else {
+            bucket.push(c);
+            in_number = false;
+        }
+    }
+    for location in [" at line N column N", " at line N, column N"] {
+        bucket = bucket.replace(location, "");
+    }
+    if bucket.len() > 120 {
+        let end = (0..=120)
+            .rev()
+            .find(|&i| bucket.is_char_boundary(i))
+            .unwrap();
+        bucket.truncate(end);
+        bucket.push_str("...");
+    }
+    bucket
+}
+
+/// How parsers disagreed on one input.
+#[derive(Clone, Debug)]
+pub enum Disagreement {
+    /// Some parsers accepted the input and others rejected it.
+    AcceptReject {
+        accepted: Vec<String>,
+        /// The parsers that rejected it, with their errors.
+        rejected: Vec<(String, String)>,
+    },
+    /// Two parsers accepted the input but produced different values.
+    Value {
+        left: String,
+        right: String,
+        /// Every difference, in key order. Never empty.
+        differences: Vec<Difference>,
+    },
+}
+
+impl Disagreement {
+    /// The category this is counted under in the summary.
+    pub fn category(&self) -> String {
+        match self {
+            Disagreement::AcceptReject { accepted, rejected } => {
+                let rejected: Vec<_> = rejected.iter().map(|(name, _)| name.as_str()).collect();
+                format!(
+                    "accepted by {}, rejected by {}",
+                    accepted.join(", "),
+                    rejected.join(", ")
+                )
+            }
+            Disagreement::Value {
+                left,
+                right,
+                differences,
+            } => format!("value mismatch {left} vs {right} ({})", differences[0].kind),
+        }
+    }
+}
+
+impl fmt::Display for Disagreement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Disagreement::AcceptReject { accepted, rejected } => {
+                write!(f, "accepted by {}", accepted.join(", "))?;
+                for (name, error) in rejected {
+                    write!(
+                        f,
+                        "\n    rejected by {name}: {}",
+                        error.replace('\n', "\n    ")
+                    )?;
+                }
+                Ok(())
+            }
+            Disagreement::Value {
+                left,
+                right,
+                differences,
+            } => {
+                write!(f, "value mismatch {left} vs {right}")?;
+                for d in differences {
+                    write!(f, "\n    {d}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Runs every parser on each input and keeps counts of disagreements and
+/// parse errors.
+pub struct Harness {
+    parsers: Vec<Box<dyn ParserUnderTest>>,
+    corpus: Option<Arc<Corpus>>,
+    checked: AtomicU64,
+    disagreements: Tally,
+    errors: Tally,
+}
+
+impl Default for Harness {
+    fn default() -> Harness {
+        Harness::new()
+    }
+}
+
+impl Harness {
+    /// Creates a harness without any parsers.
+    pub fn new() -> Harness {
+        Harness {
+            parsers: Vec::new(),
+            corpus: None,
+            checked: AtomicU64::new(0),
+            disagreements: Tally::new(5),
+            errors: Tally::new(5),
+        }
+    }
+
+    /// Adds a parser. The first parser that accepts an input is the one the
+    /// values of the others are compared against.
+    pub fn parser(mut self, parser: impl ParserUnderTest + 'static) -> Harness {
+        self.parsers.push(Box::new(parser));
+        self
+    }
+
+    /// Saves a reduced reproducer for every disagreement found by
+    /// [`Harness::report`] in `corpus`, which may be shared with other
+    /// harnesses checking the same files.
+    pub fn corpus(mut self, corpus: impl Into<Arc<Corpus>>) -> Harness {
+        self.corpus = Some(corpus.into());
+        self
+    }
+
+    /// Checks `contents` from `path` in `krate`, and prints every
+    /// disagreement with a reduced reproducer instead of the whole file.
+    pub fn report(&self, krate: &str, path: &Path, contents: &str) -> Result<()> {
+        for d in self.check(krate, contents) {
+            let reduced = self.reduce(contents, &d);
+            eprintln!("{krate} {path:?}: {d}\nreduced:\n{reduced}");
+            if let Some(corpus) = &self.corpus {
+                if let Some(saved) = corpus.add(&d.category(), &reduced)? {
+                    eprintln!("saved to {saved:?}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses `contents` from `krate` with every parser and returns how
+    /// they disagree, if at all.
+    pub fn check(&self, krate: &str, contents: &str) -> Vec<Disagreement> {
+        self.checked.fetch_add(1, Ordering::SeqCst);
+        let disagreements = self.compare(contents, |parser, e| {
+            let bucket = parser.error_bucket(e);
+            self.errors
+                .add(format!("{}: {bucket}", parser.name()), krate);
+        });
+        for d in &disagreements {
+            self.disagreements.add(d.category(), krate);
+        }
+        disagreements
+    }
+
+    /// Shrinks `contents` to a small document that still shows a
+    /// disagreement in the same category as `disagreement`, with
+    /// [`crate::reduce::reduce`].
+    pub fn reduce(&self, contents: &str, disagreement: &Disagreement) -> String {
+        let category = disagreement.category();
+        reduce::reduce(contents, |candidate| {
+            self.compare(candidate, |_, _| {})
+                .iter()
+                .any(|d| d.category() == category)
+        })
+    }
+
+    fn compare(
+        &self,
+        contents: &str,
+        mut on_error: impl FnMut(&dyn ParserUnderTest, &anyhow::Error),
+    ) -> Vec<Disagreement> {
+        let mut accepted = Vec::new();
+        let mut rejected = Vec::new();
+        for parser in &self.parsers {
+            match parser.parse(contents) {
+                Ok(value) => accepted.push((parser.name(), value)),
+                Err(e) => {
+                    on_error(parser.as_ref(), &e);
+                    rejected.push((parser.name().to_string(), format!("{e:#}")));
+                }
+            }
+        }
+
+        let mut disagreements = Vec::new();
+        if !accepted.is_empty() && !rejected.is_empty() {
+            disagreements.push(Disagreement::AcceptReject {
+                accepted: accepted.iter().map(|(name, _)| name.to_string()).collect(),
+                rejected,
+            });
+        }
+        if let Some(((left, reference), others)) = accepted.split_first() {
+            for (right, value) in others {
+                let differences = differences(reference, value);
+                if !differences.is_empty() {
+                    disagreements.push(Disagreement::Value {
+                        left: left.to_string(),
+                        right: right.to_string(),
+                        differences,
+                    });
+                }
+            }
+        }
+        disagreements
+    }
+
+    /// The counts of disagreements, by [`Disagreement::category`].
+    pub fn disagreements(&self) -> &Tally {
+        &self.disagreements
+    }
+
+    /// Prints how many inputs were checked, the disagreements and the
+    /// parse error buckets.
+    pub fn print(&self) {
+        println!("checked: {}", self.checked.load(Ordering::SeqCst));
+        self.disagreements.print("disagreements");
+        self.errors.print("parse errors");
+    }
+}
+
+/// How two values differ.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DifferenceKind {
+    /// A key or array element is only on one side.
+    KeyMissing,
+    /// The values have different types, like an integer and a float.
+    TypeChange,
+    /// Datetimes that are the same up to the separator, case or digits
+    /// beyond nanoseconds.
+    DatetimeFormatting,
+    /// Floats that differ only in the last few bits.
+    FloatPrecision,
+    /// Any other difference between values of the same type.
+    Value,
+}
+
+impl fmt::Display for DifferenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            DifferenceKind::KeyMissing => "key missing",
+            DifferenceKind::TypeChange => "type change",
+            DifferenceKind::DatetimeFormatting => "datetime formatting",
+            DifferenceKind::FloatPrecision => "float precision",
+            DifferenceKind::Value => "value",
+        };
+        f.write_str(s)
+    }
+}
+
+/// One place where two values differ.
+#[derive(Clone, Debug)]
+pub struct Difference {
+    /// The dotted path to the value, like `dependencies.serde.features[2]`.
+    pub path: String,
+    pub kind: DifferenceKind,
+    /// The values at `path`, `None` if missing on that side.
+    pub left: Option<NormalizedValue>,
+    pub right: Option<NormalizedValue>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |v: &Option<NormalizedValue>| {
+            v.as_ref()
+                .map_or_else(|| "missing".to_string(), |v| v.to_string())
+        };
+        write!(
+            f,
+            "`{}`: {}: {} vs {}",
+            self.path,
+            self.kind,
+            show(&self.left),
+            show(&self.right)
+        )
+    }
+}
+
+/// Lists every place where `a` and `b` differ, in key order. Tables and
+/// arrays are compared element by element, so a missing key is reported
+/// once instead of as a difference of the whole table.
+pub fn differences(a: &NormalizedValue, b: &NormalizedValue) -> Vec<Difference> {
+    fn walk(
+        path: &mut String,
+        a: Option<&NormalizedValue>,
+        b: Option<&NormalizedValue>,
+        out: &mut Vec<Difference>,
+    ) {
+        let len = path.len();
+        match (a, b) {
+            (Some(NormalizedValue::Table(a)), Some(NormalizedValue::Table(b))) => {
+                let mut keys: Vec<_> = a.keys().chain(b.keys()).collect();
+                keys.sort();
+                keys.dedup();
+                for k in keys {
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(&key(k));
+                    walk(path, a.get(k), b.get(k), out);
+                    path.truncate(len);
+                }
+            }
+            (Some(NormalizedValue::Array(a)), Some(NormalizedValue::Array(b))) => {
+                for i in 0..a.len().max(b.len()) {
+                    path.push_str(&format!("[{i}]"));
+                    walk(path, a.get(i), b.get(i), out);
+                    path.truncate(len);
+                }
+            }
+            (a, b) if a == b => {}
+            (a, b) => out.push(Difference {
+                path: path.clone(),
+                kind: difference_kind(a, b),
+                left: a.cloned(),
+                right: b.cloned(),
+            }),
+        }
+    }
+    let mut out = Vec::new();
+    walk(&mut String::new(), Some(a), Some(b), &mut out);
+    out
+}
+
+fn difference_kind(a: Option<&NormalizedValue>, b: Option<&NormalizedValue>) -> DifferenceKind {
+    let (Some(a), Some(b)) = (a, b) else {
+        return DifferenceKind::KeyMissing;
+    };
+    match (a, b) {
+        (NormalizedValue::Datetime(a), NormalizedValue::Datetime(b))
+            if canonical_datetime(a) == canonical_datetime(b) =>
+        {
+            DifferenceKind::DatetimeFormatting
+        }
+        (NormalizedValue::Float(a), NormalizedValue::Float(b))
+            if a.is_finite()
+                && b.is_finite()
+                && (a - b).abs() <= 4.0 * f64::EPSILON * a.abs().max(b.abs()) =>
+        {
+            DifferenceKind::FloatPrecision
+        }
+        _ if a.type_name() != b.type_name() => DifferenceKind::TypeChange,
+        _ => DifferenceKind::Value,
+    }
+}
+
+/// Rewrites a datetime with a `T` separator, upper case letters and at most
+/// nine digits of fractional seconds without trailing zeros.
+fn canonical_datetime(d: &str) -> String {
+    let d = d.to_ascii_uppercase().replacen(' ', "T", 1);
+    let Some(dot) = d.find('.') else {
+        return d;
+    };
+    let digits = d[dot + 1..]
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(d.len(), |i| dot + 1 + i);
+    let fraction = d[dot + 1..digits][..(digits - dot - 1).min(9)].trim_end_matches('0');
+    let mut canonical = d[..dot].to_string();
+    if !fraction.is_empty() {
+        canonical.push('.');
+        canonical.push_str(fraction);
+    }
+    canonical.push_str(&d[digits..]);
+    canonical
+}
+
+/// Formats a key for a path, quoting it unless it is a bare key.
+fn key(k: &str) -> String {
+    let bare = !k.is_empty()
+        && k.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if bare {
+        k.to_string()
+    } else {
+        format!("{k:?}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_bucket_strips_location_and_excerpt() {
+        let message = "TOML parse error at line 3, column 5\n  |\n3 | a = \n  |     ^\ninvalid string\nexpected `\"`, `'`\n";
+        assert_eq!(error_bucket(message), "invalid string; expected `\"`, `'`");
+    }
+
+    #[test]
+    fn error_bucket_replaces_numbers() {
+        assert_eq!(
+            error_bucket("invalid length 12, expected 3 elements at line 40 column 2"),
+            "invalid length N, expected N elements"
+        );
+        assert_eq!(
+            error_bucket("duplicate key `a` at line 1, column 10"),
+            "duplicate key `a`"
+        );
+    }
+
+    #[test]
+    fn error_bucket_is_the_same_wherever_the_error_is() {
+        let error = |s: &str| {
+            toml::from_str::<NormalizedValue>(s)
+                .unwrap_err()
+                .to_string()
+        };
+        let first = error("a = \"unterminated\n");
+        let later = error("[package]\nname = \"x\"\n\n   b = \"unterminated\n");
+        assert_ne!(first, later);
+        assert_eq!(error_bucket(&first), error_bucket(&later));
+        assert!(!error_bucket(&first).contains('|'));
+    }
+
+    fn value(toml: &str) -> NormalizedValue {
+        toml::from_str(toml).unwrap()
+    }
+
+    /// Each difference as `path: kind`.
+    fn diff(a: &NormalizedValue, b: &NormalizedValue) -> Vec<String> {
+        differences(a, b)
+            .iter()
+            .map(|d| format!("{}: {}", d.path, d.kind))
+            .collect()
+    }
+
+    #[test]
+    fn no_differences() {
+        let a = value("a = 1\nb = [1.5, nan]\n[c]\nd = 1979-05-27T07:32:00Z\n");
+        assert_eq!(differences(&a, &a.clone()).len(), 0);
+    }
+
+    #[test]
+    fn paths() {
+        let a = value("[dependencies.serde]\nfeatures = [\"std\", \"derive\", \"rc\"]\n");
+        let b = value("[dependencies.serde]\nfeatures = [\"std\", \"derive\"]\n");
+        let found = differences(&a, &b);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, "dependencies.serde.features[2]");
+        assert_eq!(found[0].left, Some(NormalizedValue::String("rc".into())));
+        assert_eq!(found[0].right, None);
+        assert_eq!(
+            found[0].to_string(),
+            "`dependencies.serde.features[2]`: key missing: \"rc\" vs missing"
+        );
+
+        let a = value("\"a.b\" = 1\n[\"sp ace\"]\nbare-key_1 = 1\n");
+        let b = value("\"a.b\" = 2\n[\"sp ace\"]\nbare-key_1 = 2\n");
+        assert_eq!(
+            diff(&a, &b),
+            ["\"a.b\": value", "\"sp ace\".bare-key_1: value"]
+        );
+    }
+
+    #[test]
+    fn missing_tables_are_one_difference() {
+        let a = value("[a]\nb = 1\nc = 2\n");
+        let b = value("x = 1\n");
+        assert_eq!(diff(&a, &b), ["a: key missing", "x: key missing"]);
+    }
+
+    #[test]
+    fn kinds() {
+        use NormalizedValue::*;
+        let cases = [
+            (Integer(1), Float(1.0), DifferenceKind::TypeChange),
+            (String("1".into()), Integer(1), DifferenceKind::TypeChange),
+            (Integer(1), Integer(2), DifferenceKind::Value),
+            (
+                String("a".into()),
+                String("b".into()),
+                DifferenceKind::Value,
+            ),
+            (Float(0.0), Float(-0.0), DifferenceKind::FloatPrecision),
+            (Float(0.1 + 0.2), Float(0.3), DifferenceKind::FloatPrecision),
+            (Float(0.3), Float(0.30001), DifferenceKind::Value),
+            (Float(f64::INFINITY), Float(f64::MAX), DifferenceKind::Value),
+            (
+                Datetime("1979-05-27T07:32:00Z".into()),
+                Datetime("1979-05-27 07:32:00z".into()),
+                DifferenceKind::DatetimeFormatting,
+            ),
+            (
+                Datetime("1979-05-27T07:32:00Z".into()),
+                Datetime("1979-05-27T07:32:01Z".into()),
+                DifferenceKind::Value,
+            ),
+            (
+                Datetime("1979-05-27".into()),
+                String("1979-05-27".into()),
+                DifferenceKind::TypeChange,
+            ),
+        ];
+        for (a, b, kind) in cases {
+            let found = differences(&a, &b);
+            assert_eq!(found.len(), 1, "{a} vs {b}");
+            assert_eq!(found[0].kind, kind, "{a} vs {b}");
+            assert_eq!(found[0].path, "");
+        }
+        assert_eq!(
+            diff(&value("a = [1]\n"), &value("a = []\n")),
+            ["a[0]: key missing"]
+        );
+    }
+
+    #[test]
+    fn canonical_datetimes() {
+        for (d, canonical) in [
+            ("1979-05-27T07:32:00Z", "1979-05-27T07:32:00Z"),
+            ("1979-05-27 07:32:00z", "1979-05-27T07:32:00Z"),
+            (
+                "1979-05-27t07:32:00.500+01:00",
+                "1979-05-27T07:32:00.5+01:00",
+            ),
+            ("1979-05-27T07:32:00.000", "1979-05-27T07:32:00"),
+            (
+                "1979-05-27T07:32:00.123456789999Z",
+                "1979-05-27T07:32:00.123456789Z",
+            ),
+            ("07:32:00.10", "07:32:00.1"),
+            ("1979-05-27", "1979-05-27"),
+        ] {
+            assert_eq!(canonical_datetime(d), canonical, "{d}");
+        }
+    }
+
+    #[test]
+    fn error_bucket_truncates() {
+        let bucket = error_bucket(&"é".repeat(100));
+        assert!(bucket.ends_with("..."));
+        assert!(bucket.len() <= 123);
+    }
+}
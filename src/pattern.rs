@@ -0,0 +1,403 @@
+//! Matching patterns of tokens, for questions that don't need a syntax
+//! tree.
+//!
+//! A [`Pattern`] is written as Rust tokens, with a few additions:
+//!
+//! - `$ident`, `$lit`, `$punct` and `$tt` match any one identifier,
+//!   literal, punctuation character or token tree (including a whole
+//!   group), and capture it under that name.
+//! - `$name:kind` does the same but captures under `name`, like `$mac:ident`.
+//! - `...` matches any tokens, possibly none.
+//! - `( )`, `[ ]` and `{ }` match a group with the same delimiter whose
+//!   contents match the pattern inside, all of it.
+//!
+//! Everything else matches the same token. For example,
+//! `#[cfg(feature = $lit)]` finds `cfg` attributes on a single feature and
+//! captures the feature name, and `$ident ! ( ... )` finds macro calls with
+//! parentheses. A pattern can match starting at any token of a file,
+//! inside groups too.
+
+use crate::findings::Span;
+use crate::literal::Literal;
+use anyhow::{bail, format_err, Result};
+use proc_macro2::{Delimiter, Group, TokenStream, TokenTree};
+use std::fmt;
+use std::str::FromStr;
+
+/// A compiled token pattern. See the [module documentation](self).
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    source: String,
+    nodes: Vec<Node>,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    /// An identifier, punctuation or literal that must be the same.
+    Token(String),
+    Group(Delimiter, Vec<Node>),
+    Capture {
+        name: String,
+        kind: Kind,
+    },
+    /// `...`
+    Any,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Ident,
+    Lit,
+    Punct,
+    Tt,
+}
+
+/// Where a pattern matched, and what it captured.
+#[derive(Clone, Debug)]
+pub struct Match {
+    pub span: Span,
+    pub captures: Vec<Capture>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Capture {
+    pub name: String,
+    pub span: Span,
+    /// The captured tokens, as printed by `proc_macro2`.
+    pub text: String,
+    /// The decoded value, if a literal was captured.
+    pub literal: Option<Literal>,
+}
+
+impl FromStr for Pattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Pattern> {
+        let tokens: TokenStream = s
+            .parse()
+            .map_err(|e| format_err!("invalid pattern `{s}`: {e}"))?;
+        let nodes = compile(tokens).map_err(|e| format_err!("invalid pattern `{s}`: {e}"))?;
+        if nodes.iter().all(|n| matches!(n, Node::Any)) {
+            bail!("pattern `{s}` matches nothing in particular");
+        }
+        Ok(Pattern {
+            source: s.to_string(),
+            nodes,
+        })
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn compile(tokens: TokenStream) -> Result<Vec<Node>> {
+    let tokens: Vec<_> = tokens.into_iter().collect();
+    let mut nodes = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            TokenTree::Punct(p) if p.as_char() == '$' => {
+                let Some(TokenTree::Ident(name)) = tokens.get(i + 1) else {
+                    bail!("expected a name or kind after `$`");
+                };
+                let name = name.to_string();
+                let (kind, consumed) = match (tokens.get(i + 2), tokens.get(i + 3)) {
+                    (Some(TokenTree::Punct(colon)), Some(TokenTree::Ident(kind)))
+                        if colon.as_char() == ':' =>
+                    {
+                        (kind.to_string(), 4)
+                    }
+                    _ => (name.clone(), 2),
+                };
+                let kind = match kind.as_str() {
+                    "ident" => Kind::Ident,
+                    "lit" => Kind::Lit,
+                    "punct" => Kind::Punct,
+                    "tt" => Kind::Tt,
+                    _ => bail!("unknown kind `${kind}`, expected `ident`, `lit`, `punct` or `tt`"),
+                };
+                nodes.push(Node::Capture { name, kind });
+                i += consumed;
+            }
+            TokenTree::Punct(p) if p.as_char() == '.' && is_ellipsis(&tokens[i..]) => {
+                nodes.push(Node::Any);
+                i += 3;
+            }
+            TokenTree::Group(g) => {
+                nodes.push(Node::Group(g.delimiter(), compile(g.stream())?));
+                i += 1;
+            }
+            tt => {
+                nodes.push(Node::Token(tt.to_string()));
+                i += 1;
+            }
+        }
+    }
+    Ok(nodes)
+}
+
+fn is_ellipsis(tokens: &[TokenTree]) -> bool {
+    tokens.len() >= 3
+        && tokens[..3]
+            .iter()
+            .all(|tt| matches!(tt, TokenTree::Punct(p) if p.as_char() == '.'))
+}
+
+/// A token tree with the contents of groups already collected, so they
+/// can be matched against repeatedly.
+enum Tree {
+    Leaf(TokenTree),
+    Group(Group, Vec<Tree>),
+}
+
+impl Tree {
+    fn collect(tokens: TokenStream) -> Vec<Tree> {
+        tokens
+            .into_iter()
+            .map(|tt| match tt {
+                TokenTree::Group(g) => {
+                    let inner = Tree::collect(g.stream());
+                    Tree::Group(g, inner)
+                }
+                tt => Tree::Leaf(tt),
+            })
+            .collect()
+    }
+
+    fn span(&self) -> proc_macro2::Span {
+        match self {
+            Tree::Leaf(tt) => tt.span(),
+            Tree::Group(g, _) => g.span(),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Tree::Leaf(tt) => tt.to_string(),
+            Tree::Group(g, _) => g.to_string(),
+        }
+    }
+}
+
+impl Pattern {
+    /// Finds every match in `contents`, which must be valid Rust tokens.
+    pub fn find(&self, contents: &str) -> Result<Vec<Match>> {
+        let tokens: TokenStream = contents.parse().map_err(|e| format_err!("{e}"))?;
+        Ok(self.find_in(tokens))
+    }
+
+    /// Finds every match in `tokens`, in order of where they start.
+    pub fn find_in(&self, tokens: TokenStream) -> Vec<Match> {
+        let mut found = Vec::new();
+        self.search(&Tree::collect(tokens), &mut found);
+        found.sort_by_key(|m| m.span.start);
+        found
+    }
+
+    fn search(&self, trees: &[Tree], found: &mut Vec<Match>) {
+        for start in 0..trees.len() {
+            let mut captures = Vec::new();
+            if let Some(len) = match_seq(&self.nodes, &trees[start..], false, &mut captures) {
+                if len > 0 {
+                    found.push(Match {
+                        span: Span {
+                            start: Span::from(trees[start].span()).start,
+                            end: Span::from(trees[start + len - 1].span()).end,
+                        },
+                        captures,
+                    });
+                }
+            }
+            if let Tree::Group(_, inner) = &trees[start] {
+                self.search(inner, found);
+            }
+        }
+    }
+}
+
+/// Matches `nodes` against the start of `trees`, or all of them if
+/// `anchored`, returning how many trees matched. `...` matches as few
+/// trees as it can.
+fn match_seq(
+    nodes: &[Node],
+    trees: &[Tree],
+    anchored: bool,
+    captures: &mut Vec<Capture>,
+) -> Option<usize> {
+    let Some((node, rest)) = nodes.split_first() else {
+        return (!anchored || trees.is_empty()).then_some(0);
+    };
+    let mark = captures.len();
+    if let Node::Any = node {
+        for skip in 0..=trees.len() {
+            if let Some(len) = match_seq(rest, &trees[skip..], anchored, captures) {
+                return Some(skip + len);
+            }
+            captures.truncate(mark);
+        }
+        return None;
+    }
+    let tree = trees.first()?;
+    if match_one(node, tree, captures) {
+        if let Some(len) = match_seq(rest, &trees[1..], anchored, captures) {
+            return Some(len + 1);
+        }
+    }
+    captures.truncate(mark);
+    None
+}
+
+fn match_one(node: &Node, tree: &Tree, captures: &mut Vec<Capture>) -> bool {
+    match (node, tree) {
+        (Node::Token(expected), Tree::Leaf(tt)) => *expected == tt.to_string(),
+        (Node::Group(delimiter, nodes), Tree::Group(g, inner)) => {
+            *delimiter == g.delimiter() && match_seq(nodes, inner, true, captures).is_some()
+        }
+        (Node::Capture { name, kind }, tree) => {
+            let matched = matches!(
+                (kind, tree),
+                (Kind::Tt, _)
+                    | (Kind::Ident, Tree::Leaf(TokenTree::Ident(_)))
+                    | (Kind::Lit, Tree::Leaf(TokenTree::Literal(_)))
+                    | (Kind::Punct, Tree::Leaf(TokenTree::Punct(_)))
+            );
+            if matched {
+                captures.push(Capture {
+                    name: name.clone(),
+                    span: tree.span().into(),
+                    text: tree.text(),
+                    literal: match tree {
+                        Tree::Leaf(TokenTree::Literal(lit)) => Literal::new(lit),
+                        _ => None,
+                    },
+                });
+            }
+            matched
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(s: &str) -> Pattern {
+        s.parse().unwrap()
+    }
+
+    /// The source text of each match, for patterns without captures.
+    fn matches(pattern_src: &str, contents: &str) -> Vec<String> {
+        pattern(pattern_src)
+            .find(contents)
+            .unwrap()
+            .iter()
+            .map(|m| contents[m.span.byte_range(contents)].to_string())
+            .collect()
+    }
+
+    /// The captures of each match, as `name = text`.
+    fn captures(pattern_src: &str, contents: &str) -> Vec<Vec<String>> {
+        pattern(pattern_src)
+            .find(contents)
+            .unwrap()
+            .iter()
+            .map(|m| {
+                m.captures
+                    .iter()
+                    .map(|c| format!("{} = {}", c.name, c.text))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(matches("a . b", "x.a.b(); a . b"), ["a.b", "a . b"]);
+        assert_eq!(matches("a . b", "a.c.b"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn ellipsis_backtracks() {
+        // `...` first tries to match nothing, then one more token at a
+        // time, until the rest matches.
+        assert_eq!(matches("a ... b", "a b"), ["a b"]);
+        assert_eq!(matches("a ... b", "a x y b b"), ["a x y b"]);
+        assert_eq!(matches("a ... b c", "a b x b c"), ["a b x b c"]);
+        // Inside a group, everything has to match, so `...` takes the rest.
+        assert_eq!(matches("f ( ... )", "f(1, 2) f()"), ["f(1, 2)", "f()"]);
+        assert_eq!(matches("f ( 1 ... )", "f(1, 2) f(2, 1)"), ["f(1, 2)"]);
+        assert_eq!(matches("f ( ... 1 )", "f(1, 2) f(2, 1)"), ["f(2, 1)"]);
+    }
+
+    #[test]
+    fn captures_by_kind() {
+        assert_eq!(
+            captures("$ident ! ( $lit )", r#"println!("a") vec!(1) f!(x)"#),
+            [
+                vec![r#"ident = println"#, r#"lit = "a""#],
+                vec!["ident = vec", "lit = 1"]
+            ]
+        );
+        assert_eq!(
+            captures("$mac:ident ! $args:tt", "a!(1) b![2]"),
+            [vec!["mac = a", "args = (1)"], vec!["mac = b", "args = [2]"]]
+        );
+        assert_eq!(captures("x $punct y", "x + y x y"), [vec!["punct = +"]]);
+    }
+
+    #[test]
+    fn repeated_captures() {
+        assert_eq!(
+            captures("$a:ident + $a:ident", "x + y + z"),
+            [vec!["a = x", "a = y"], vec!["a = y", "a = z"]]
+        );
+    }
+
+    #[test]
+    fn literal_captures() {
+        let found = pattern("#[cfg(feature = $lit)]")
+            .find(r#"#[cfg(feature = "s\x74d")] fn f() {}"#)
+            .unwrap();
+        let literal = found[0].captures[0].literal.as_ref().unwrap();
+        assert_eq!(literal.text().as_deref(), Some("std"));
+    }
+
+    #[test]
+    fn groups_by_delimiter() {
+        assert_eq!(matches("f ( $tt )", "f(1) f[2] f{3}"), ["f(1)"]);
+        assert_eq!(matches("f [ $tt ]", "f(1) f[2] f{3}"), ["f[2]"]);
+        assert_eq!(matches("f { $tt }", "f(1) f[2] f{3}"), ["f{3}"]);
+        // Matches inside groups are found too.
+        assert_eq!(matches("a b", "{ (a b) [x a b] }"), ["a b", "a b"]);
+    }
+
+    #[test]
+    fn compile_errors() {
+        for (src, error) in [
+            ("$", "expected a name or kind after `$`"),
+            ("$1", "expected a name or kind after `$`"),
+            ("$x", "unknown kind `$x`"),
+            ("$x:expr", "unknown kind `$expr`"),
+            ("...", "matches nothing in particular"),
+            ("( ... )", ""),
+            ("a )", "invalid pattern `a )`"),
+        ] {
+            match src.parse::<Pattern>() {
+                Ok(_) => assert!(error.is_empty(), "`{src}` should not compile"),
+                Err(e) => assert!(
+                    !error.is_empty() && e.to_string().contains(error),
+                    "`{src}`: {e}"
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(pattern("$x:ident ! (...)").to_string(), "$x:ident ! (...)");
+    }
+}
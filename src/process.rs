@@ -0,0 +1,174 @@
+//! Running external processes.
+//!
+//! Commands run by scanners operate on untrusted crates, so they are run
+//! with [`Limits`]: a wall-clock timeout and a memory limit. Each process is
+//! started in its own process group so that a timeout kills everything it
+//! spawned (such as `rustc` or build scripts), not just the top process.
+
+use anyhow::{Context, Result};
+use std::fmt;
+use std::io::Read;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// Limits applied to a process started with [`run`].
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Limits {
+    /// Wall-clock time after which the process and all of its children are
+    /// killed.
+    pub timeout: Option<Duration>,
+    /// Maximum virtual memory of each process, in bytes (`RLIMIT_AS`).
+    ///
+    /// This applies to every process separately, not to the group as a
+    /// whole, and is ignored on platforms without `setrlimit`.
+    pub memory: Option<u64>,
+}
+
+/// The result of running a process with [`run`].
+#[derive(Debug)]
+pub struct Output {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+    /// Wall-clock time from spawning until the process exited.
+    pub duration: Duration,
+    /// Whether the process was killed because it exceeded its timeout.
+    pub timed_out: bool,
+}
+
+impl Output {
+    /// Whether the process finished on its own and exited successfully.
+    pub fn success(&self) -> bool {
+        !self.timed_out && self.status.success()
+    }
+
+    /// Returns a [`TimedOut`] error if the process was killed by its
+    /// timeout.
+    pub fn check_timeout(&self) -> Result<()> {
+        if self.timed_out {
+            return Err(TimedOut {
+                duration: self.duration,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Error for a process that was killed because it exceeded its timeout.
+///
+/// Scanners can return this so that timeouts are counted separately from
+/// other failures in the scan summary.
+#[derive(Debug)]
+pub struct TimedOut {
+    pub duration: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out after {:.1}s", self.duration.as_secs_f64())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Returns whether `e` was caused by a [`TimedOut`] error.
+pub fn is_timeout(e: &anyhow::Error) -> bool {
+    e.chain().any(|e| e.is::<TimedOut>())
+}
+
+/// Runs `cmd` to completion with the given limits, capturing its output.
+///
+/// If the timeout is exceeded, the process group is killed and the returned
+/// output has `timed_out` set. Stdin is closed.
+pub fn run(cmd: &mut Command, limits: &Limits) -> Result<Output> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+        if let Some(memory) = limits.memory {
+            // SAFETY: `setrlimit` is async-signal-safe and only affects the
+            // child.
+            unsafe {
+                cmd.pre_exec(move || {
+                    let limit = libc::rlimit {
+                        rlim_cur: memory as libc::rlim_t,
+                        rlim_max: memory as libc::rlim_t,
+                    };
+                    if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+    }
+    let start = Instant::now();
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("failed to spawn {cmd:?}"))?;
+
+    // Read both pipes on their own threads so a process that fills one of
+    // them doesn't block forever.
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let stdout = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+    let stderr = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        stderr.read_to_end(&mut buf).map(|_| buf)
+    });
+
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if limits.timeout.is_some_and(|t| start.elapsed() > t) {
+            timed_out = true;
+            kill_group(&mut child)?;
+            break child.wait()?;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    let duration = start.elapsed();
+    // Anything left behind would keep the pipes open.
+    kill_group(&mut child)?;
+    let stdout = stdout.join().unwrap()?;
+    let stderr = stderr.join().unwrap()?;
+    Ok(Output {
+        status,
+        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        duration,
+        timed_out,
+    })
+}
+
+#[cfg(unix)]
+fn kill_group(child: &mut Child) -> Result<()> {
+    // SAFETY: sending a signal has no memory safety requirements.
+    let r = unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+    if r != 0 {
+        let e = std::io::Error::last_os_error();
+        // The group is already gone.
+        if e.raw_os_error() != Some(libc::ESRCH) {
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill_group(child: &mut Child) -> Result<()> {
+    match child.kill() {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,253 @@
+//! Shrinking inputs that show a bug down to a minimal reproducer.
+//!
+//! [`reduce`] uses delta debugging: it repeatedly removes chunks of the
+//! input, keeping a removal whenever the result is still interesting, first
+//! over whole TOML tables and then over single lines. A [`Corpus`] stores
+//! the reduced inputs, skipping ones it already has, so they can be used as
+//! regression tests.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Reduces `input` to a smaller document for which `interesting` still
+/// returns true. `interesting(input)` should be true to begin with.
+pub fn reduce(input: &str, mut interesting: impl FnMut(&str) -> bool) -> String {
+    let tables = ddmin(split_tables(input), &mut interesting);
+    let lines: Vec<_> = tables
+        .iter()
+        .flat_map(|t| t.split_inclusive('\n'))
+        .collect();
+    ddmin(lines, &mut interesting).concat()
+}
+
+/// Splits a TOML document before every line that starts a table, keeping
+/// the keys before the first table as their own chunk.
+fn split_tables(input: &str) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    for line in input.split_inclusive('\n') {
+        if line.trim_start().starts_with('[') && offset > start {
+            chunks.push(&input[start..offset]);
+            start = offset;
+        }
+        offset += line.len();
+    }
+    if offset > start {
+        chunks.push(&input[start..offset]);
+    }
+    chunks
+}
+
+/// Finds a small subset of `units` (keeping their order) whose
+/// concatenation is still interesting.
+fn ddmin<'a>(mut units: Vec<&'a str>, interesting: &mut impl FnMut(&str) -> bool) -> Vec<&'a str> {
+    let mut n = 2;
+    while units.len() >= 2 {
+        let chunk = units.len().div_ceil(n);
+        let mut reduced = false;
+        for start in (0..units.len()).step_by(chunk) {
+            let end = (start + chunk).min(units.len());
+            let subset = &units[start..end];
+            if interesting(&subset.concat()) {
+                units = subset.to_vec();
+                n = 2;
+                reduced = true;
+                break;
+            }
+            let complement: Vec<_> = units[..start]
+                .iter()
+                .chain(&units[end..])
+                .copied()
+                .collect();
+            if interesting(&complement.concat()) {
+                units = complement;
+                n = (n - 1).max(2);
+                reduced = true;
+                break;
+            }
+        }
+        if !reduced {
+            if n >= units.len() {
+                break;
+            }
+            n = (n * 2).min(units.len());
+        }
+    }
+    units
+}
+
+/// A directory of reduced inputs, with a subdirectory per category and
+/// files named after the hash of their contents.
+pub struct Corpus {
+    dir: PathBuf,
+    extension: String,
+    seen: Mutex<HashSet<PathBuf>>,
+}
+
+impl Corpus {
+    /// Uses `dir` for the corpus, creating it if needed. Files are written
+    /// with `extension`, like `toml`.
+    pub fn create(dir: impl AsRef<Path>, extension: &str) -> Result<Corpus> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {dir:?}"))?;
+        Ok(Corpus {
+            dir: dir.to_path_buf(),
+            extension: extension.to_string(),
+            seen: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Adds `contents` under `category`. Returns the path of the new file,
+    /// or `None` if the same contents were already in the corpus.
+    pub fn add(&self, category: &str, contents: &str) -> Result<Option<PathBuf>> {
+        let hash = format!("{:x}", Sha256::digest(contents.as_bytes()));
+        let path =
+            self.dir
+                .join(slug(category))
+                .join(format!("{}.{}", &hash[..16], self.extension));
+        if !self.seen.lock().unwrap().insert(path.clone()) || path.exists() {
+            return Ok(None);
+        }
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, contents).with_context(|| format!("failed to write {path:?}"))?;
+        Ok(Some(path))
+    }
+}
+
+/// Turns a category into a directory name, like
+/// `accepted-by-toml-0-5-rejected-by-toml-0-7`.
+fn slug(category: &str) -> String {
+    let mut slug = String::new();
+    for c in category.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = "\
+name = \"top\"
+
+[package]
+name = \"x\"
+version = \"0.1.0\"
+edition = \"2021\"
+
+[dependencies]
+anyhow = \"1\"
+serde = { version = \"1\", features = [\"derive\"] }
+syn = \"2\"
+
+[dev-dependencies]
+serde = \"1\"
+
+[features]
+default = [\"std\"]
+std = []
+";
+
+    #[test]
+    fn split_tables_keeps_everything() {
+        let tables = split_tables(MANIFEST);
+        assert_eq!(tables.len(), 5);
+        assert_eq!(tables[0], "name = \"top\"\n\n");
+        assert!(tables[1].starts_with("[package]\n"));
+        assert_eq!(tables.concat(), MANIFEST);
+        assert_eq!(split_tables("a = 1\nb = 2"), ["a = 1\nb = 2"]);
+        assert_eq!(split_tables(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn reduces_to_the_interesting_lines() {
+        let interesting =
+            |s: &str| s.contains("[dependencies]") && s.contains("features = [\"derive\"]");
+        let reduced = reduce(MANIFEST, interesting);
+        assert!(interesting(&reduced));
+        assert_eq!(
+            reduced,
+            "[dependencies]\nserde = { version = \"1\", features = [\"derive\"] }\n"
+        );
+    }
+
+    #[test]
+    fn tables_before_lines() {
+        let interesting = |s: &str| s.contains("[features]") && s.contains("std = []");
+        let tables = split_tables(MANIFEST);
+
+        // What the table pass alone tries, and what it keeps.
+        let mut table_calls = 0;
+        let kept = ddmin(tables.clone(), &mut |s: &str| {
+            table_calls += 1;
+            interesting(s)
+        });
+        assert_eq!(kept.concat(), "[features]\ndefault = [\"std\"]\nstd = []\n");
+
+        let mut candidates = Vec::new();
+        let reduced = reduce(MANIFEST, |s| {
+            candidates.push(s.to_string());
+            interesting(s)
+        });
+        assert_eq!(reduced, "[features]\nstd = []\n");
+        assert!(reduced.len() < kept.concat().len());
+        // The first candidates are made of whole tables, the rest of lines
+        // of the table that was kept.
+        let is_whole_tables = |candidate: &str| {
+            let mut rest = candidate;
+            for table in &tables {
+                rest = rest.strip_prefix(table).unwrap_or(rest);
+            }
+            rest.is_empty()
+        };
+        assert!(table_calls > 0);
+        assert!(candidates[..table_calls].iter().all(|c| is_whole_tables(c)));
+        let kept_lines: Vec<_> = kept.iter().flat_map(|t| t.lines()).collect();
+        assert!(candidates[table_calls..]
+            .iter()
+            .all(|c| c.lines().all(|line| kept_lines.contains(&line))));
+    }
+
+    #[test]
+    fn ddmin_finds_scattered_units() {
+        let units: Vec<String> = (0..32).map(|i| format!("{i}\n")).collect();
+        let units: Vec<&str> = units.iter().map(String::as_str).collect();
+        let mut calls = 0;
+        let kept = ddmin(units, &mut |s: &str| {
+            calls += 1;
+            s.lines().any(|l| l == "3") && s.lines().any(|l| l == "29")
+        });
+        assert_eq!(kept, ["3\n", "29\n"]);
+        // Far fewer than the 2^32 subsets.
+        assert!(calls < 200, "{calls} calls");
+    }
+
+    #[test]
+    fn keeps_input_that_cannot_shrink() {
+        let input = "a = 1\nb = 2\n";
+        assert_eq!(reduce(input, |s| s == input), input);
+    }
+
+    #[test]
+    fn corpus_skips_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let corpus = Corpus::create(dir.path(), "toml").unwrap();
+        let category = "accepted by toml 0.5, rejected by toml 0.7";
+        let saved = corpus.add(category, "a = 1\n").unwrap().unwrap();
+        assert!(saved.starts_with(dir.path().join("accepted-by-toml-0-5-rejected-by-toml-0-7")));
+        assert_eq!(fs::read_to_string(&saved).unwrap(), "a = 1\n");
+        assert_eq!(corpus.add(category, "a = 1\n").unwrap(), None);
+        let again = Corpus::create(dir.path(), "toml").unwrap();
+        assert_eq!(again.add(category, "a = 1\n").unwrap(), None);
+    }
+}
@@ -0,0 +1,119 @@
+//! Structured results from scanners.
+//!
+//! A [`Sink`] writes one JSON object per line, which is easy to load into
+//! other tools or to compare between runs. A [`Tally`] keeps counts per
+//! category for the summary printed at the end of a scan.
+
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Writes results as JSON lines, shared between scanner threads.
+pub struct Sink {
+    out: Mutex<BufWriter<File>>,
+}
+
+impl Sink {
+    /// Creates or truncates the file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Sink> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("failed to create {path:?}"))?;
+        Ok(Sink {
+            out: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Writes one record.
+    pub fn write(&self, record: &serde_json::Value) -> Result<()> {
+        let mut out = self.out.lock().unwrap();
+        serde_json::to_writer(&mut *out, record)?;
+        out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Flushes buffered records, which is otherwise only done when the sink
+    /// is dropped.
+    pub fn flush(&self) -> Result<()> {
+        self.out.lock().unwrap().flush()?;
+        Ok(())
+    }
+}
+
+/// Counts occurrences per category, along with how many distinct crates
+/// they came from and a few example crates.
+pub struct Tally {
+    max_examples: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+#[derive(Default)]
+struct Entry {
+    count: u64,
+    crates: HashSet<String>,
+    examples: Vec<String>,
+}
+
+impl Tally {
+    /// Creates a tally that keeps up to `max_examples` example crates per
+    /// category.
+    pub fn new(max_examples: usize) -> Tally {
+        Tally {
+            max_examples,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records one occurrence of `category` in `krate`.
+    pub fn add(&self, category: impl Into<String>, krate: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(category.into()).or_default();
+        entry.count += 1;
+        if entry.crates.insert(krate.to_string()) && entry.examples.len() < self.max_examples {
+            entry.examples.push(krate.to_string());
+        }
+    }
+
+    /// The number of occurrences of `category`.
+    pub fn count(&self, category: &str) -> u64 {
+        let entries = self.entries.lock().unwrap();
+        entries.get(category).map_or(0, |e| e.count)
+    }
+
+    /// The number of distinct crates `category` was seen in.
+    pub fn distinct_crates(&self, category: &str) -> usize {
+        let entries = self.entries.lock().unwrap();
+        entries.get(category).map_or(0, |e| e.crates.len())
+    }
+
+    /// Returns the categories, most common first.
+    pub fn categories(&self) -> Vec<String> {
+        let entries = self.entries.lock().unwrap();
+        let mut categories: Vec<_> = entries.iter().collect();
+        categories.sort_by(|(a_name, a), (b_name, b)| {
+            b.count.cmp(&a.count).then_with(|| a_name.cmp(b_name))
+        });
+        categories
+            .into_iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Prints every category under `title`, most common first.
+    pub fn print(&self, title: &str) {
+        println!("{title}:");
+        let categories = self.categories();
+        let entries = self.entries.lock().unwrap();
+        for name in categories {
+            let entry = &entries[&name];
+            println!(
+                "    {name}: {} ({} crates), e.g. {}",
+                entry.count,
+                entry.crates.len(),
+                entry.examples.join(", ")
+            );
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Choosing which crates and versions to scan.
+
+use crate::CrateId;
+use anyhow::{bail, format_err, Context, Result};
+use semver::Version;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Which versions of each crate to include.
+#[derive(Clone, Debug)]
+pub enum Versions {
+    All,
+    Latest,
+    /// The `n` highest versions of each crate.
+    LatestN(usize),
+    /// The highest version of each crate published on or before `date`
+    /// (`YYYY-MM-DD`).
+    ///
+    /// Publish times come from the `pubtime` field in the index at
+    /// `index_path`. Versions without a `pubtime` were published before
+    /// crates.io started recording it, and are treated as older than any
+    /// date.
+    AsOf {
+        date: String,
+        index_path: PathBuf,
+    },
+    /// Exactly the given versions.
+    List(Vec<CrateId>),
+}
+
+/// A set of crates to scan or extract.
+#[derive(Clone, Debug)]
+pub struct Selection {
+    pub versions: Versions,
+    /// If set, only crates with these names are included.
+    pub crates: Option<HashSet<String>>,
+}
+
+impl From<Versions> for Selection {
+    fn from(versions: Versions) -> Selection {
+        Selection {
+            versions,
+            crates: None,
+        }
+    }
+}
+
+pub const USAGE: &str = "\
+selection options:
+    --all               every version of every crate
+    --latest            the highest version of every crate
+    --latest-n N        the N highest versions of every crate
+    --as-of DATE        the highest version published on or before DATE
+                        (YYYY-MM-DD), requires --index
+    --index PATH        path to a clone of the crates.io index
+    --list FILE         versions listed in FILE, one `name@version` per line
+    --crate NAME        only include the given crate (may be repeated)";
+
+impl Selection {
+    /// Parses the selection options in [`USAGE`] from command-line arguments.
+    ///
+    /// `default` is used if no version option is given. Arguments that are
+    /// not selection options are returned in order so the caller can handle
+    /// them.
+    pub fn from_args(
+        default: Versions,
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(Selection, Vec<String>)> {
+        let mut versions = None;
+        let mut as_of = None;
+        let mut index_path = None;
+        let mut crates: Option<HashSet<String>> = None;
+        let mut rest = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format_err!("`{arg}` requires a value"))
+            };
+            match arg.as_str() {
+                "--all" => versions = Some(Versions::All),
+                "--latest" => versions = Some(Versions::Latest),
+                "--latest-n" => {
+                    let n = value()?;
+                    let n = n
+                        .parse()
+                        .with_context(|| format!("invalid --latest-n value `{n}`"))?;
+                    versions = Some(Versions::LatestN(n));
+                }
+                "--as-of" => as_of = Some(value()?),
+                "--index" => index_path = Some(PathBuf::from(value()?)),
+                "--list" => {
+                    let path = value()?;
+                    let contents = std::fs::read_to_string(&path)
+                        .with_context(|| format!("failed to read {path:?}"))?;
+                    let ids = contents
+                        .lines()
+                        .map(str::trim)
+                        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                        .map(|line| line.parse())
+                        .collect::<Result<_>>()?;
+                    versions = Some(Versions::List(ids));
+                }
+                "--crate" => {
+                    crates.get_or_insert_with(HashSet::new).insert(value()?);
+                }
+                _ => rest.push(arg.clone()),
+            }
+        }
+        if let Some(date) = as_of {
+            let Some(index_path) = index_path else {
+                bail!("--as-of requires --index");
+            };
+            versions = Some(Versions::AsOf { date, index_path });
+        }
+        let selection = Selection {
+            versions: versions.unwrap_or(default),
+            crates,
+        };
+        Ok((selection, rest))
+    }
+
+    /// Returns the paths of the selected `.crate` files in a crates mirror.
+    pub fn collect(&self, crates_path: &Path) -> Vec<PathBuf> {
+        let mut by_name: HashMap<String, Vec<(Version, PathBuf)>> = HashMap::new();
+        for entry in walkdir::WalkDir::new(crates_path) {
+            let entry = entry.unwrap();
+            if !entry.file_name().to_str().unwrap().ends_with(".crate") {
+                continue;
+            }
+            let path = entry.into_path();
+            let Some(id) = CrateId::from_crate_path(&path) else {
+                eprintln!("warning: could not determine crate version from {path:?}");
+                continue;
+            };
+            if self.crates.as_ref().is_some_and(|c| !c.contains(&id.name)) {
+                continue;
+            }
+            by_name.entry(id.name).or_default().push((id.version, path));
+        }
+
+        let list: HashSet<_> = match &self.versions {
+            Versions::List(ids) => ids.iter().collect(),
+            _ => HashSet::new(),
+        };
+        by_name
+            .into_iter()
+            .flat_map(|(name, mut versions)| {
+                versions.sort_by(|a, b| b.0.cmp(&a.0));
+                match &self.versions {
+                    Versions::All => {}
+                    Versions::Latest => versions.truncate(1),
+                    Versions::LatestN(n) => versions.truncate(*n),
+                    Versions::AsOf { date, index_path } => {
+                        let pubtimes = read_pubtimes(index_path, &name);
+                        versions.retain(|(version, _)| {
+                            pubtimes
+                                .get(version)
+                                .and_then(|t| t.get(..10))
+                                .is_none_or(|d| d <= date.as_str())
+                        });
+                        versions.truncate(1);
+                    }
+                    Versions::List(_) => versions.retain(|(version, _)| {
+                        list.contains(&CrateId {
+                            name: name.clone(),
+                            version: version.clone(),
+                        })
+                    }),
+                }
+                versions.into_iter().map(|(_, path)| path)
+            })
+            .collect()
+    }
+}
+
+/// Reads the `pubtime` of every version of a crate from the index.
+fn read_pubtimes(index_path: &Path, name: &str) -> HashMap<Version, String> {
+    let path = index_path
+        .join(crate::layout::shard(name))
+        .join(name.to_lowercase());
+    let Ok(contents) = std::fs::read_to_string(&path) else {
+        eprintln!("warning: no index entry for {name} at {path:?}");
+        return HashMap::new();
+    };
+    contents
+        .lines()
+        .filter_map(|line| {
+            let entry: serde_json::Value = serde_json::from_str(line).ok()?;
+            let version = Version::parse(entry["vers"].as_str()?).ok()?;
+            let pubtime = entry.get("pubtime")?.as_str()?.to_string();
+            Some((version, pubtime))
+        })
+        .collect()
+}